@@ -0,0 +1,184 @@
+/*
+ * annot.rs - facts about a binary given by the user rather than found
+ *  by decoding it, from the command line or an annotation file
+ */
+use std::{
+    fs,
+    io,
+    fmt::Display,
+};
+
+use crate::parse;
+use crate::instrset::{Instrset, Word, DataRange};
+use crate::ir::{self, ModeMap, DataMap};
+
+/*
+ * Addresses <start> up to (but not including) <end> are in mode <mode>.
+ * Without an <end>, until the next range that starts later
+ */
+pub struct ModeRange {
+    pub start: u64,
+    pub end: Option<u64>,
+    pub mode: String,
+}
+
+#[derive(Default)]
+pub struct Annotations {
+    pub modes: Vec<ModeRange>,
+    pub data: Vec<DataRange>,
+}
+
+/*
+ * Why an annotation file could not be used
+ */
+pub enum AnnotErr {
+    Open(String,io::Error), // the file at the path could not be read
+    Bad(String),            // a line of it is wrong, and why
+}
+
+impl Display for AnnotErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self {
+            AnnotErr::Open(path,why) => write!(f,"Couldn't open annotation file {}: {}",path,why),
+            AnnotErr::Bad(why) => write!(f,"{}",why),
+        }
+    }
+}
+
+/*
+ * Read an address range like "0x100:0x200" or "0x100"
+ */
+fn parse_range(text: &str) -> Result<(u64,Option<u64>),String> {
+    let num=|t: &str| parse::parse_number::<u64>(t)
+        .map_err(|why| format!("Couldn't parse \"{}\" as an address: {}",t,why));
+    match text.split_once(':') {
+        Some((a,b)) => Ok((num(a)?,Some(num(b)?))),
+        None => Ok((num(text)?,None)),
+    }
+}
+
+/*
+ * Read a --mode argument: a mode name for the whole binary, or a range
+ *  and a mode like "0x100:0x200=thumb"
+ */
+pub fn parse_mode_arg(text: &str) -> Result<ModeRange,String> {
+    match text.split_once('=') {
+        Some((range,mode)) => {
+            let (start,end)=parse_range(range)?;
+            Ok(ModeRange {start, end, mode: mode.to_string()})
+        },
+        None => Ok(ModeRange {start: 0, end: None, mode: text.to_string()}),
+    }
+}
+
+/*
+ * Add the annotations in file <path> to <ann>. Each line is a range
+ *  and what is in it, like:
+ *  0x100:0x200 mode thumb
+ *  0x200:0x240 data word
+ * where data is byte, half, word, ascii or skip.
+ * Lines starting with # are comments
+ */
+pub fn read_file(path: &str, ann: &mut Annotations) -> Result<(),AnnotErr> {
+    let text=fs::read_to_string(path)
+        .map_err(|why| AnnotErr::Open(path.to_string(),why))?;
+
+    for (n,line) in text.lines().enumerate() {
+        let words: Vec<&str>=line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {continue}
+
+        let bad=|why: String| AnnotErr::Bad(format!("{} line {}: {}",path,n+1,why));
+        match words[..] {
+            [range,"mode",mode] => {
+                let (start,end)=parse_range(range).map_err(bad)?;
+                ann.modes.push(ModeRange {start, end, mode: mode.to_string()});
+            },
+            [range,"data",kind] => {
+                let r=parse::parse_data_range(range,kind).map_err(|why| bad(why.to_string()))?;
+                ann.data.push(r);
+            },
+            _ => {return Err(bad(format!("Expected a range and a mode or data \
+                                          (like \"0x100:0x200 mode thumb\" or \"0x200:0x240 data word\"). \
+                                          Found:\n{}",line)))},
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Modes of the code in a binary decoded with <is>, from <ann>.
+ * Ranges are applied in order, so later ones win where they overlap
+ */
+pub fn mode_map<W: Word>(ann: &Annotations, is: &Instrset<W>) -> Result<ModeMap,String> {
+    let mut ret=ModeMap::new();
+    for r in &ann.modes {
+        let m=is.mode_index(&r.mode)
+            .ok_or_else(|| format!("The script has no mode called \"{}\"",r.mode))?;
+        ir::set_mode(&mut ret,r.start,r.end,m);
+    }
+    Ok(ret)
+}
+
+/*
+ * Ranges of data in a binary decoded with <is>: those in the script,
+ *  and then those in <ann>, so later ones win where they overlap.
+ * Every range must hold a whole number of items, each a whole number
+ *  of addresses
+ */
+pub fn data_map<W: Word>(ann: &Annotations, is: &Instrset<W>) -> Result<DataMap,String> {
+    let abytes=is.address_bytes();
+    let mut ret=DataMap::new();
+    for r in is.data.iter().chain(&ann.data) {
+        let size=r.kind.size().unwrap_or(abytes);
+        if abytes==0 {
+            return Err("Data ranges need words stored in whole bytes".to_string())
+        }
+        if !size.is_multiple_of(abytes) {
+            return Err(format!("Data of kind {} does not fill whole addresses of {} bytes",r.kind.keyword(),abytes))
+        }
+        if !((r.end-r.start)*abytes).is_multiple_of(size) {
+            return Err(format!("Data range {:#x}:{:#x} does not hold a whole number of {} items",r.start,r.end,r.kind.keyword()))
+        }
+        ir::set_data(&mut ret,r.start,r.end,r.kind);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(text: &str) -> Instrset<u64> {
+        match parse::parse_file(text.as_bytes()) {
+            Ok(is) => is,
+            Err((why,ln)) => panic!("line {}: {}",ln,why),
+        }
+    }
+
+    #[test]
+    fn test_data_map() {
+        // ranges from the annotations win over those in the script
+        let is=script("1 byte words\ndata 0x0:0x10 word\nmask 0xff {\n0x0 = nop\n}\n");
+        let mut ann=Annotations::default();
+        let Ok(r)=parse::parse_data_range("0x4:0x8","ascii") else {panic!("Range failed")};
+        ann.data.push(r);
+        let data=match data_map(&ann,&is) {
+            Ok(data) => data,
+            Err(why) => panic!("{}",why),
+        };
+        let got: Vec<(u64,u64,&str)>=data.iter().map(|(a,(e,k))| (*a,*e,k.keyword())).collect();
+        assert!(got==[(0x0,0x4,"word"),(0x4,0x8,"ascii"),(0x8,0x10,"word")],"Actual: {:?}",got);
+
+        for (text,want) in [
+            ("1 byte words\ndata 0x0:0x6 word\nmask 0xff {\n0x0 = nop\n}\n",
+                "Data range 0x0:0x6 does not hold a whole number of word items"),
+            ("2 byte words\ndata 0x0:0x2 byte\nmask 0xff {\n0x0 = nop\n}\n",
+                "Data of kind byte does not fill whole addresses of 2 bytes"),
+        ] {
+            match data_map(&Annotations::default(),&script(text)) {
+                Ok(_) => panic!("{:?} accepted",text),
+                Err(why) => assert!(why==want,"Actual: {}",why),
+            }
+        }
+    }
+}
@@ -2,126 +2,378 @@
  * file.rs - helpers for reading binary files
  */
 use std::{
-    io::{
-        BufReader,
-        Seek, SeekFrom,
-        Read,
-    },
     fs::File,
+    io::{self, Read},
+    ops::Range,
+    collections::BTreeMap,
+    sync::{
+        mpsc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    marker::PhantomData,
 };
 
 #[path="bits.rs"]
 pub mod bits;
-pub use bits::Wordt as Wordt;
+pub use bits::Word as Word;
+
+/*
+ * Number of words handed to a worker thread at a time
+ */
+pub const CHUNK_WORDS: u64 = 1<<16;
+
+/*
+ * How instruction words are stored in a binary
+ */
+#[derive(Clone)]
+pub struct Layout {
+    pub wordbits: usize,     // bits in an instruction word
+    pub container: usize,    // bytes each word is stored in; 0 for a packed bit stream
+    pub endian_little: bool, // if false use big endian (and most significant bit first when packed)
+    pub check_padding: bool, // container bits above <wordbits> must be 0
+    /*
+     * Optional permutation of the bytes of each container, applied
+     *  before the endianness: byte k of the reordered container is byte
+     *  byte_order[k] of the file. E.g. [1,0,3,2] with big endian reads
+     *  two little endian halfwords stored in big endian order
+     */
+    pub byte_order: Option<Vec<usize>>,
+}
+
+impl Layout {
+    /*
+     * Plain bytes
+     */
+    pub fn bytes() -> Layout {
+        Layout {wordbits: 8, container: 1, endian_little: true, check_padding: false, byte_order: None}
+    }
+
+    /*
+     * Bits a Word needs to hold one stored word
+     */
+    pub fn storage_bits(&self) -> usize {
+        self.wordbits.max(8*self.container)
+    }
+
+    /*
+     * Script header line for the byte order, if there is one
+     */
+    pub fn byte_order_line(&self) -> Option<String> {
+        self.byte_order.as_ref().map(|order| {
+            let order: Vec<String>=order.iter().map(|b| b.to_string()).collect();
+            format!("byte order {}",order.join(" "))
+        })
+    }
+}
+
+/*
+ * Layout in script syntax, i.e. the first line of a script
+ */
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
+        let endian=match self.endian_little {true=>"", false=>"nonnative endian "};
+        if self.container==0 {
+            write!(f,"{} bit {}packed words",self.wordbits,endian)
+        }
+        else if self.wordbits==8*self.container {
+            write!(f,"{} byte {}words",self.container,endian)
+        }
+        else {
+            write!(f,"{} bit {}{}words in {} byte containers",self.wordbits,endian,
+                   match self.check_padding {true=>"checked ", false=>""},self.container)
+        }
+    }
+}
 
-pub struct Binreader {
-    buffer: Vec<u8>,
-    reader: BufReader<File>,
+/*
+ * The whole binary image, loaded once.
+ * Words are decoded straight from the loaded bytes, so any number of
+ *  passes (and threads) can share one Binreader.
+ */
+pub struct Binreader<W: Word> {
+    data: Vec<u8>,
+    layout: Layout,
     pub n_instrs: u64,
-    endian_little: bool,
+    word: PhantomData<W>,
 }
 
 pub enum BinReaderErr {
-    InternalIO(std::io::Error),
-    Rewind(std::io::Error),
+    Open(std::io::Error),
+    BadWordsize(usize,u32),
+    BadFilesize(u64,Layout),
 }
 
 impl std::fmt::Display for BinReaderErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
     -> Result<(),std::fmt::Error> {
         match self {
-            BinReaderErr::InternalIO(why) =>
-                write!(f,"Failed to read from binary file: {}",why),
-            BinReaderErr::Rewind(why) =>
-                write!(f,"Failed to rewind binary file: {}",why),
+            BinReaderErr::Open(why) =>
+                write!(f,"{}",why),
+            BinReaderErr::BadWordsize(ws,max) =>
+                write!(f,"Word size ({} bits) does not fit in {} bits",ws,max),
+            BinReaderErr::BadFilesize(fsize,l) if l.container==0 =>
+                write!(f,"Filesize ({} bytes) does not hold a whole number of {} bit words",fsize,l.wordbits),
+            BinReaderErr::BadFilesize(fsize,l) =>
+                write!(f,"Filesize ({}) is not a multiple of wordsize ({})",fsize,l.container),
         }
     }
 }
 
-impl Binreader {
+impl<W: Word> Binreader<W> {
+    /*
+     * Size of the file in bytes
+     */
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /*
+     * Bytes <range> of the file
+     */
+    pub fn bytes(&self, range: Range<usize>) -> &[u8] {
+        &self.data[range]
+    }
+
+    /*
+     * Word number <i> of the file, without any padding bits.
+     * <i> must be less than n_instrs
+     */
+    pub fn word(&self, i: u64) -> W {
+        if self.layout.container==0 {return self.packed_word(i)}
+        self.container(&self.layout,i as usize*self.layout.container) & bits::low_bits(self.layout.wordbits)
+    }
+
+    /*
+     * Padding bits (container bits above the word) of word number <i>,
+     *  if the layout says they must be checked. Otherwise 0.
+     */
+    pub fn padding(&self, i: u64) -> W {
+        if !self.layout.check_padding {return W::ZERO}
+        self.container(&self.layout,i as usize*self.layout.container) & !bits::low_bits::<W>(self.layout.wordbits)
+    }
+
+    /*
+     * Word of <layout> stored at byte <start>, and its padding bits if
+     *  <layout> says they must be checked (otherwise 0). None if the
+     *  file ends before the word does.
+     * <layout> must have containers, i.e. not be packed
+     */
+    pub fn word_at(&self, layout: &Layout, start: u64) -> Option<(W,W)> {
+        let start=start as usize;
+        if start+layout.container>self.data.len() {return None}
+        let c=self.container(layout,start);
+        let low=bits::low_bits::<W>(layout.wordbits);
+        Some((c&low, match layout.check_padding {true => c&!low, false => W::ZERO}))
+    }
+
     /*
-     * Next word from file.
-     * May fail on internal file errors
+     * Whole container of <layout> starting at byte <start>
      */
-    pub fn next(&mut self) -> Result<Wordt,BinReaderErr> {
-        match self.reader.read_exact(&mut self.buffer) {
-            Ok(()) => Ok(match self.endian_little {
-                    true  => bits::wordt_from_le(&self.buffer),
-                    false => bits::wordt_from_be(&self.buffer),
-                }),
-            Err(why) => Err(BinReaderErr::InternalIO(why)),
+    fn container(&self, layout: &Layout, start: usize) -> W {
+        let size=layout.container;
+        let mut buf=[0u8; bits::MAX_WORD_BITS/8];
+        let bytes=match &layout.byte_order {
+            None => &self.data[start..start+size],
+            Some(order) => {
+                for (k,from) in order.iter().enumerate() {buf[k]=self.data[start+from];}
+                &buf[..size]
+            },
+        };
+        match layout.endian_little {
+            true  => W::from_le_slice(bytes),
+            false => W::from_be_slice(bytes),
         }
     }
 
     /*
-     * Rewind binary file back to the start
+     * Word number <i> of a packed bit stream.
+     * Little endian streams hold each word least significant bit first,
+     *  starting from the lowest bit of each byte; big endian streams the
+     *  other way around.
      */
-    pub fn rewind(&mut self) -> Result<(),BinReaderErr> {
-        match self.reader.rewind() {
-            Ok(()) => Ok(()),
-            Err(why) => Err(BinReaderErr::Rewind(why)),
+    fn packed_word(&self, i: u64) -> W {
+        let n=self.layout.wordbits;
+        let mut bit=i as usize*n;
+        let mut got=0;
+        let mut w=W::ZERO;
+
+        while got<n {
+            let byte=self.data[bit/8];
+            let b=bit%8;
+            let take=(8-b).min(n-got);
+            let low=(1u16<<take)-1;
+            if self.layout.endian_little {
+                let v=(byte as u16>>b)&low;
+                w|=W::from_u64(v as u64)<<got as u32;
+            }
+            else {
+                let v=(byte as u16>>(8-b-take))&low;
+                w=(w<<take as u32)|W::from_u64(v as u64);
+            }
+            got+=take;
+            bit+=take;
         }
+        w
     }
 
     /*
-     * New Binary file reader with given wordsize
+     * Split the file into consecutive ranges of at most CHUNK_WORDS words
+     */
+    pub fn chunks(&self) -> Vec<Range<u64>> {
+        let mut ret=Vec::new();
+        let mut start=0;
+        while start<self.n_instrs {
+            let end=self.n_instrs.min(start+CHUNK_WORDS);
+            ret.push(start..end);
+            start=end;
+        }
+        ret
+    }
+
+    /*
+     * New Binary file reader for words stored as in <layout>.
+     * The file is read into memory in one go, so it may be any stream:
+     *  a path of "-" reads standard input, and pipes or FIFOs work too.
      * May fail if:
-     *  - wordsize cannot fit into a u64
-     *  - Error opening file
-     *  - file size cannot be read
-     *  - file size is not a multiple of wordsize
+     *  - words cannot fit into a W
+     *  - Error reading file
+     *  - file size is not a whole number of words
      */
-    pub fn new(wordsize: usize, filepath: &String, endian_little: bool) 
-    -> Option<Binreader> {
-        let fsize: u64;
-        let ws: u64= match wordsize.try_into() {
-            Ok(u) => u,
-            Err(_) => {return None},
-        };
+    pub fn new(filepath: &String, layout: &Layout)
+    -> Result<Binreader<W>,BinReaderErr> {
+        if filepath=="-" {
+            Binreader::from_reader(io::stdin().lock(), layout)
+        }
+        else { match File::open(filepath) {
+            Ok(f) => Binreader::from_reader(f, layout),
+            Err(why) => Err(BinReaderErr::Open(why)),
+        }}
+    }
+
+    /*
+     * New Binary file reader over everything left in <reader>.
+     * Nothing is assumed about <reader> besides being readable until EOF
+     */
+    pub fn from_reader<R: Read>(mut reader: R, layout: &Layout)
+    -> Result<Binreader<W>,BinReaderErr> {
+        if layout.wordbits==0 || layout.storage_bits()>W::BITS as usize {
+            return Err(BinReaderErr::BadWordsize(layout.storage_bits(),W::BITS))
+        }
+
+        let mut data=Vec::new();
+        if let Err(why)=reader.read_to_end(&mut data) {
+            return Err(BinReaderErr::Open(why))
+        }
 
-        // open file
-        let mut f: File = match File::open(filepath) {
-            Ok(file) => file,
-            Err(why) => {
-                eprintln!("Couldn't open binary file {}: {}",
-                          filepath, why);
-                return None
+        // check if wordsize is ok
+        let n_instrs=match layout.container {
+            // packed words may only leave the last byte partly unused
+            0 => {
+                let n=data.len()*8/layout.wordbits;
+                if (n*layout.wordbits).div_ceil(8)!=data.len() {
+                    return Err(BinReaderErr::BadFilesize(data.len() as u64,layout.clone()))
+                }
+                n
+            },
+            c => {
+                if data.len()%c != 0 {
+                    return Err(BinReaderErr::BadFilesize(data.len() as u64,layout.clone()))
+                }
+                data.len()/c
             },
         };
 
+        Ok(Binreader {
+            n_instrs: n_instrs as u64,
+            data,
+            layout: layout.clone(),
+            word: PhantomData,
+        })
+    }
+}
 
-        // get filesize in bytes
-        if let Err(why)=f.seek(SeekFrom::End(0)) {
-            eprintln!("Internal error in file seek: {}",why);
-            return None
-        }
-        match f.stream_position() {
-            Ok(p)    => {fsize = p;},
-            Err(why) => {
-                eprintln!("Internal error fetching filesize: {}",why);
-                return None
-            },
+/*
+ * Run <work> on every item of <items> using up to <jobs> threads.
+ * Results are handed to <collect> in the order of <items>. If <collect>
+ *  returns false, no further items are started or collected.
+ */
+pub fn par_map<I,T,F,C>(items: &[I], jobs: usize, work: F, mut collect: C)
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> T + Sync,
+    C: FnMut(T) -> bool,
+{
+    let jobs=jobs.clamp(1,items.len().max(1));
+    let next=AtomicUsize::new(0);
+    let stop=AtomicBool::new(false);
+    let (tx,rx)=mpsc::channel::<(usize,T)>();
+
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx=tx.clone();
+            let (next,stop,work)=(&next,&stop,&work);
+            s.spawn(move || loop {
+                let i=next.fetch_add(1,Ordering::SeqCst);
+                if i>=items.len() || stop.load(Ordering::SeqCst) {return}
+                if tx.send((i,work(&items[i]))).is_err() {return}
+            });
         }
-        if let Err(why)=f.rewind() {
-            eprintln!("Internal error in file seek: {}",why);
-            return None
+        drop(tx);
+
+        // hand results over in order
+        let mut pending: BTreeMap<usize,T>=BTreeMap::new();
+        let mut want: usize=0;
+        for (i,res) in rx.iter() {
+            pending.insert(i,res);
+            while let Some(res)=pending.remove(&want) {
+                want+=1;
+                if !collect(res) {
+                    stop.store(true,Ordering::SeqCst);
+                    return
+                }
+            }
         }
+    })
+}
 
-        // check if wordsize is ok
-        if fsize%ws != 0 {
-            eprintln!("Filesize ({}) is not a multiple of wordsize ({})",fsize,ws);
-            return None
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(bytes: &[u8], wordbits: usize, endian_little: bool) -> Binreader<u64> {
+        let layout=Layout {wordbits, container: 0, endian_little, check_padding: false, byte_order: None};
+        match Binreader::from_reader(bytes,&layout) {
+            Ok(br) => br,
+            Err(why) => panic!("{}",why),
         }
+    }
 
-        // create buffer
-        let mut buffer=Vec::<u8>::with_capacity(wordsize);
-        buffer.resize(wordsize,0);
+    #[test]
+    fn test_packed_little() {
+        let br=packed(&[0xab,0xcd,0xef],12,true);
+        assert!(br.n_instrs==2,"Actual: {}",br.n_instrs);
+        assert!(br.word(0)==0xdab && br.word(1)==0xefc,
+            "Actual: {:#x} {:#x}",br.word(0),br.word(1));
+    }
 
-        Some(Binreader {
-            buffer: buffer,
-            reader: BufReader::new(f),
-            n_instrs: fsize/ws,
-            endian_little: endian_little,
-        })
+    #[test]
+    fn test_packed_big() {
+        let br=packed(&[0xab,0xcd,0xef],12,false);
+        assert!(br.word(0)==0xabc && br.word(1)==0xdef,
+            "Actual: {:#x} {:#x}",br.word(0),br.word(1));
+    }
+
+    #[test]
+    fn test_packed_odd_width() {
+        // 0b10110_01101_01110_1: the last bit is left over
+        let br=packed(&[0b10110011,0b01011101],5,false);
+        let words: Vec<u64>=(0..br.n_instrs).map(|i| br.word(i)).collect();
+        assert!(words==[0b10110,0b01101,0b01110],"Actual: {:?}",words);
+
+        let br=packed(&[0b10110011,0b01011101],5,true);
+        let words: Vec<u64>=(0..br.n_instrs).map(|i| br.word(i)).collect();
+        assert!(words==[0b10011,0b01101,0b10111],"Actual: {:?}",words);
     }
 }
@@ -1,30 +1,102 @@
+use std::{
+    fmt,
+    hash::Hash,
+    num::ParseIntError,
+    ops::{
+        BitAnd, BitOr, BitXor, Not, Shl, Shr, Add,
+        BitAndAssign, BitOrAssign, ShlAssign, ShrAssign,
+    },
+};
+
 /*
- * Wordt must be an unsigned numeric type large enough to store
- *  any word in instruction sets being parsed. At minimum it is 8 bytes.
- * 
- * Wordt must implement these traits: 
- *    Clone
- *
- * Wordt must have a from_str_radix function.
- * It must also have a field BITS which stores the type's size in bits
+ * Word is implemented by the unsigned numeric types that can hold
+ *  the words of an instruction set being parsed.
+ * Everything working on words is generic over Word, so the type can be
+ *  picked to fit the script: u64 for words up to 8 bytes (the fast
+ *  path), u128 for wider words such as VLIW bundles.
  *
- * u64 or u128 are the only tested types!
+ * Signed is for formatting signed numbers. It is the signed numeric
+ *  type with the same size as the Word.
  */
-pub type Wordt = u64;
+pub trait Word:
+    Copy + Eq + Ord + Hash + Send + Sync + 'static
+    + fmt::Display + fmt::LowerHex + fmt::Binary
+    + BitAnd<Output=Self> + BitOr<Output=Self> + BitXor<Output=Self> + Not<Output=Self>
+    + Shl<u32,Output=Self> + Shr<u32,Output=Self> + Add<Output=Self>
+    + BitAndAssign + BitOrAssign + ShlAssign<u32> + ShrAssign<u32>
+{
+    type Signed: Copy + fmt::Display + Into<i128>;
+    const BITS: u32;
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_str_radix(text: &str, radix: u32) -> Result<Self,ParseIntError>;
+    /* <bytes> must not be longer than the Word */
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    fn from_be_slice(bytes: &[u8]) -> Self;
+    /* Lowest 64 bits */
+    fn to_u64(self) -> u64;
+    fn from_u64(n: u64) -> Self;
+    fn count_ones(self) -> u32;
+    /* Same bits, as a Signed */
+    fn to_signed(self) -> Self::Signed;
+    fn wrapping_neg(self) -> Self;
+    fn neg_signed(s: Self::Signed) -> Self::Signed;
+}
+
+macro_rules! impl_word {
+    ($t:ty, $s:ty) => {
+        impl Word for $t {
+            type Signed = $s;
+            const BITS: u32 = <$t>::BITS;
+            const ZERO: $t = 0;
+            const ONE: $t = 1;
+
+            fn from_str_radix(text: &str, radix: u32) -> Result<$t,ParseIntError> {
+                <$t>::from_str_radix(text,radix)
+            }
+            fn from_le_slice(bytes: &[u8]) -> $t {
+                let mut buf=[0u8; std::mem::size_of::<$t>()];
+                buf[..bytes.len()].copy_from_slice(bytes);
+                <$t>::from_le_bytes(buf)
+            }
+            fn from_be_slice(bytes: &[u8]) -> $t {
+                let mut buf=[0u8; std::mem::size_of::<$t>()];
+                let n=buf.len();
+                buf[n-bytes.len()..].copy_from_slice(bytes);
+                <$t>::from_be_bytes(buf)
+            }
+            fn to_u64(self) -> u64 { self as u64 }
+            fn from_u64(n: u64) -> $t { n as $t }
+            fn count_ones(self) -> u32 { <$t>::count_ones(self) }
+            fn to_signed(self) -> $s { self as $s }
+            fn wrapping_neg(self) -> $t { <$t>::wrapping_neg(self) }
+            fn neg_signed(s: $s) -> $s { s.wrapping_neg() }
+        }
+    };
+}
+impl_word!(u64, i64);
+impl_word!(u128, i128);
+
 /*
- * Signt is for formatting signed numbers.
- * It should be a signed numeric type with capacity (Number of bits)
- *  with identical size as Wordt, and the same trait restrictions.
- * 
- * Only i64 and i128 are tested.
+ * Widest word any script can use, in bits
  */
-pub type Signt = i64;
+pub const MAX_WORD_BITS: usize = 128;
 
-/* Bitmask is an alias to Wordt */
-pub type Bitmask = Wordt;
+/*
+ * Only the highest bit set
+ */
+fn maxbit<W: Word>() -> W { W::ONE << (W::BITS-1) }
 
-const MAXBIT: Wordt=1 << (Wordt::BITS-1);
+/*
+ * Bitmask of the lowest <n> bits
+ */
+pub fn low_bits<W: Word>(n: usize) -> W {
+    if n>=W::BITS as usize {!W::ZERO}
+    else {!(!W::ZERO << n as u32)}
+}
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum BitOpType {
     AND,
     OR,
@@ -32,24 +104,40 @@ pub enum BitOpType {
     SL, SR // bitshifts
 }
 
+impl BitOpType {
+    /*
+     * Operator for this BitOpType in a script
+     */
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BitOpType::AND => "&",
+            BitOpType::OR => "|",
+            BitOpType::XOR => "^",
+            BitOpType::SL => "<<",
+            BitOpType::SR => ">>",
+        }
+    }
+}
+
 /*
  * Represents performing operation <typ> with operand <val>
- *  onto some existing Wordt
+ *  onto some existing word
  */
-pub struct BitOp {
+pub struct BitOp<W: Word> {
     pub typ: BitOpType,
-    pub val: Wordt,
+    pub val: W,
 }
-pub fn apply_bit_ops<'a,I>(ops: I, w: &mut Wordt)
+pub fn apply_bit_ops<'a,W,I>(ops: I, w: &mut W)
 where
-    I: Iterator<Item = &'a BitOp>,
+    W: Word,
+    I: Iterator<Item = &'a BitOp<W>>,
 {
     for op in ops {*w=match op.typ {
         BitOpType::AND => *w&op.val,
         BitOpType::OR => *w|op.val,
         BitOpType::XOR => *w^op.val,
-        BitOpType::SL => *w<<op.val,
-        BitOpType::SR => *w>>op.val,
+        BitOpType::SL => *w<<op.val.to_u64() as u32,
+        BitOpType::SR => *w>>op.val.to_u64() as u32,
     }}
 }
 
@@ -57,17 +145,15 @@ where
  * Two's complement to obtain a signed integer from
  *  the first <size> bits of <w>
  */
-pub fn twoscomp((w,size): (Wordt, Wordt)) -> Signt {
-    // Copy bits from w to ret
-    let ret: Signt = Signt::from_le_bytes(w.to_le_bytes());
+pub fn twoscomp<W: Word>((w,size): (W, W)) -> W::Signed {
+    let size=size.to_u64() as u32;
     // sign bit=0 (positive)
-    if 0==w&(1<<(size-1)) { return ret }
+    if size==0 || W::ZERO==w&(W::ONE<<(size-1)) { w.to_signed() }
     else {
-        //return (-1*(ret & ((1<<(size-1))-1)))+1
-        return   -1*
-                 ((!ret+1)      // negation
-                 & (1<<size)-1) // ...of relevant bits
-                 
+        W::neg_signed(
+            (w.wrapping_neg()                // negation
+            & low_bits::<W>(size as usize))  // ...of relevant bits
+            .to_signed())
     }
 }
 
@@ -77,13 +163,13 @@ pub fn twoscomp((w,size): (Wordt, Wordt)) -> Signt {
  * <w> must already be under <mask>
  * TODO rework. Currently slow
  */
-pub fn align(mut w: Wordt, mut mask: Bitmask) -> Wordt {
-    let mut ret: Wordt=0;
+pub fn align<W: Word>(mut w: W, mut mask: W) -> W {
+    let mut ret: W=W::ZERO;
 
-    for _ in 0..(Wordt::BITS) {
+    for _ in 0..(W::BITS) {
         ret >>= 1;
-        if mask&1 != 0 {
-            if w&1 != 0 {ret |= MAXBIT;}
+        if mask&W::ONE != W::ZERO {
+            if w&W::ONE != W::ZERO {ret |= maxbit();}
             w >>= 1;
         }
         mask >>= 1;
@@ -96,16 +182,17 @@ pub fn align(mut w: Wordt, mut mask: Bitmask) -> Wordt {
  *  adjacent and as left shifted as much as possible.
  * Returns (<n> under <mask>, number of high bits in <mask>)
  */
-// TODO rework. Currently very slow (64 loops every time)
-pub fn minimize(mut w: Wordt, mut mask: Bitmask) -> (Wordt,Wordt) {
-    let mut ret: Wordt=0;
-    let mut size: Wordt=0; // mask size
+// TODO rework. Currently very slow (one loop per bit every time)
+pub fn minimize<W: Word>(mut w: W, mut mask: W) -> (W,W) {
+    let mut ret: W=W::ZERO;
+    let mut size: W=W::ZERO; // mask size
+    let top: W=maxbit();
 
-    for _ in 0..(Wordt::BITS) {
-        if mask&MAXBIT !=0 {
+    for _ in 0..(W::BITS) {
+        if mask&top != W::ZERO {
             ret<<=1;
-            if 0!=w&MAXBIT { ret |= 1; }
-            size+=1;
+            if W::ZERO!=w&top { ret |= W::ONE; }
+            size=size+W::ONE;
         }
         w<<=1;
         mask<<=1;
@@ -115,36 +202,13 @@ pub fn minimize(mut w: Wordt, mut mask: Bitmask) -> (Wordt,Wordt) {
 }
 
 /*
- * Reverse <size> bits in <w>
+ * Reverse the lowest <size> bits in <w>
  */
-pub fn reverse(w: &Wordt, size: usize) -> Wordt {
-    let mut ret: Wordt = 0;
-    for i in 0..(8*size) {
+pub fn reverse<W: Word>(w: &W, size: usize) -> W {
+    let mut ret: W = W::ZERO;
+    for i in 0..(size as u32) {
         ret<<=1;
-        if w&(1<<i)!=0 {ret|=1;}
+        if *w&(W::ONE<<i)!=W::ZERO {ret|=W::ONE;}
     }
     ret
 }
-
-/*
- * Make an unsigned number from little endian bytes
- */
-pub fn wordt_from_le(bytes: &Vec<u8>) -> Wordt {
-    let mut ret: Wordt=0;
-    for i in 0..bytes.len() {
-        ret|= (bytes[i] as Wordt) << (8*i)
-    }
-    return ret
-}
-/*
- * Make an unsigned number from big endian bytes
- */
-pub fn wordt_from_be(bytes: &Vec<u8>) -> Wordt {
-    let mut ret: Wordt=0;
-    for i in 0..bytes.len() {
-        ret|= (bytes[bytes.len()-i-1] as Wordt) << (8*i)
-    }
-    return ret
-}
-
-
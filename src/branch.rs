@@ -1,77 +1,162 @@
 use std::{
+    io,
     fmt::Display,
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
 };
 
 
 #[path="instrset.rs"]
 pub mod instrset;
-use instrset::{
-    binreader::Binreader,
-    FmtType,
-    Maskmap,
-    bits as bits, bits::{
-        Wordt, Bitmask,
-    },
-};
+
+#[path="ir.rs"]
+pub mod ir;
+
+#[path="symbols.rs"]
+pub mod symbols;
+use ir::Instr;
+use instrset::{Word, FmtType, Class};
 
 
 
 pub type BranchTree = BTreeSet<u64>;
 
 
-pub enum GenLabelsErr {
-    Unrecognized(u64, Wordt),
-    Binread(u64,instrset::binreader::BinReaderErr),
+
+/*
+ * Collect the label of every branch in the decoded instructions <instrs>
+ */
+pub fn gen_labels<W: Word>(instrs: &[Instr<W>]) -> BranchTree {
+    let mut tree=BranchTree::new();
+    for ins in instrs {
+        for op in ins.ops() {
+            if let Some(t)=op.target {tree.insert(t);}
+        }
+    }
+    tree
+}
+
+/*
+ * How a label is referred to: from after it (backward) or before it,
+ *  by a distance (relative) or its address (absolute), and by what class
+ *  of instruction
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RefKind {
+    pub backward: bool,
+    pub relative: bool,
+    pub class: Option<Class>,
 }
-impl Display for GenLabelsErr {
+
+impl Display for RefKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
-        match self {
-            GenLabelsErr::Unrecognized(when,what) =>
-                write!(f,"[At {:#x}] Unknown instruction: {:#x}",when,what),
-            GenLabelsErr::Binread(when,why) =>
-                write!(f,"[At {:#x}] I/O error: {}",when,why),
+        write!(f,"{} {}",
+               match self.backward {true=>"backward", false=>"forward"},
+               match self.relative {true=>"relative", false=>"absolute"})?;
+        match self.class {
+            Some(c) => write!(f," {}",c.keyword()),
+            None => Ok(()),
+        }
+    }
+}
+
+/*
+ * Every kind of reference to each label in the decoded instructions
+ *  <instrs>
+ */
+pub fn label_kinds<W: Word>(instrs: &[Instr<W>]) -> BTreeMap<u64,BTreeSet<RefKind>> {
+    let mut ret: BTreeMap<u64,BTreeSet<RefKind>>=BTreeMap::new();
+    for ins in instrs {
+        for op in ins.ops() {
+            if let Some(t)=op.target {
+                ret.entry(t).or_default().insert(RefKind {
+                    backward: t<=ins.addr,
+                    relative: !matches!(op.fmt.typ,FmtType::Sbranch),
+                    class: ins.class(),
+                });
+            }
         }
     }
+    ret
+}
+
+/*
+ * A reference to label <to>: the instruction it is made from, and the
+ *  format keyword of the operand making it
+ */
+pub struct Xref {
+    pub to: u64,
+    pub from: u64,
+    pub kind: &'static str,
+}
+
+/*
+ * References to every label, in one list sorted by label, and those to
+ *  each label in the order of the instructions making them
+ */
+pub struct Xrefs {
+    refs: Vec<Xref>,
 }
 
+impl Xrefs {
+    /*
+     * No references
+     */
+    pub fn new() -> Xrefs {
+        Xrefs {refs: Vec::new()}
+    }
+
+    /*
+     * References to the label at <to>
+     */
+    pub fn get(&self, to: u64) -> &[Xref] {
+        let start=self.refs.partition_point(|r| r.to<to);
+        let end=start+self.refs[start..].partition_point(|r| r.to==to);
+        &self.refs[start..end]
+    }
+
+    /*
+     * Every label referred to, in order, with the references to it
+     */
+    pub fn labels(&self) -> impl Iterator<Item=(u64,&[Xref])> {
+        self.refs.chunk_by(|a,b| a.to==b.to).map(|refs| (refs[0].to,refs))
+    }
+}
 
+/*
+ * Every reference made by a branch or address operand in the decoded
+ *  instructions <instrs>. Address operands are in bytes, and refer to
+ *  the label of <unit> bytes they are in
+ */
+pub fn gen_xrefs<W: Word>(instrs: &[Instr<W>], unit: u64) -> Xrefs {
+    let mut ret=Vec::new();
+    for ins in instrs {
+        for op in ins.ops() {
+            let to=match (&op.fmt.typ,op.target) {
+                (_,Some(t)) => t,
+                (FmtType::Addr,None) => op.val.0.to_u64()/unit,
+                _other => continue,
+            };
+            ret.push(Xref {to, from: ins.addr, kind: op.fmt.typ.keyword()});
+        }
+    }
+    // stable, so the references to a label stay in instruction order
+    ret.sort_by_key(|r| r.to);
+    Xrefs {refs: ret}
+}
 
 /*
- * Add any labels in the file wrapped by <br> who branch upwards to the
- *  labels set <tree>
+ * Write every label in <xrefs> to <out>, named from <symbols> if it
+ *  has a symbol, with the address and operand kind of each reference
  */
-pub fn add_branch_ups(br: &mut Binreader, tree: &mut BranchTree, set: &Maskmap) -> Result<(),GenLabelsErr> {
-    let mut mask: Bitmask=0; // just so get_fmt can track it
-    let mut dest: (Wordt, Wordt);
-
-    for i in 0..br.n_instrs { match br.next() {
-        Ok(w) => { match instrset::get_fmt(w, set, &mut mask) {
-            Some((_name,ifmt)) => { for f in &ifmt.fmt {
-                dest = bits::minimize(w,f.mask);
-
-                bits::apply_bit_ops(f.ops.iter(), &mut dest.0);
-
-                match &f.typ {
-                    FmtType::Ubranch => {tree.insert(i-dest.0);},
-                    FmtType::Sbranch => { if dest.0<i {tree.insert(dest.0);} },
-                    FmtType::Ibranch => { if dest.0<=0 {tree.insert(
-                                i
-                                -bits::Wordt::from_le_bytes((-1*bits::twoscomp(dest)).to_le_bytes())
-                                );}},
-
-                    _other => (),
-                }
-            }},
-            None => {
-                return Err(GenLabelsErr::Unrecognized(i,w))
-            },
-        }},
-        Err(why) => { return Err(GenLabelsErr::Binread(i,why)) },
-    }}
-
-    match br.rewind() {
-        Ok(()) => Ok(()),
-        Err(why) => Err(GenLabelsErr::Binread(br.n_instrs,why)),
+pub fn write_xrefs<O: io::Write>(xrefs: &Xrefs, symbols: &symbols::Symbols, out: &mut O) -> io::Result<()> {
+    for (to,refs) in xrefs.labels() {
+        match symbols.name(to) {
+            Some(name) => writeln!(out,"{:#x} {}:",to,name)?,
+            None => writeln!(out,"{:#x}:",to)?,
+        }
+        for r in refs {
+            writeln!(out,"    {:#x} {}",r.from,r.kind)?;
+        }
     }
+    out.flush()
 }
@@ -0,0 +1,223 @@
+/*
+ * cfg.rs - control flow graphs of decoded instructions, written as
+ *  Graphviz DOT
+ */
+use std::{
+    io,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+};
+
+use super::{
+    render,
+    RenderOpts,
+    Instr,
+    bits::Word,
+    branch::BranchTree,
+    instrset::Class,
+};
+
+/*
+ * Why control can go from one block to another
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Edge {
+    FallThrough, // the block just runs into the next one
+    Taken,       // branch taken
+    NotTaken,    // conditional branch not taken
+}
+
+/*
+ * Instructions <start>..<end> (indexes into the decoded instructions),
+ *  which only run one after the other, and where control goes after
+ */
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub succs: Vec<(u64,Edge)>,
+}
+
+/*
+ * Whether nothing but the next instruction can follow <ins>.
+ * Data never runs, so nothing follows it
+ */
+fn carries_on<W: Word>(ins: &Instr<W>) -> bool {
+    if ins.data.is_some() {return false}
+    match ins.class() {
+        Some(Class::Call) => true,
+        Some(_) => false,
+        None => ins.ops().all(|op| op.target.is_none()),
+    }
+}
+
+/*
+ * Split <instrs> into basic blocks, starting at <entries>, at branch
+ *  targets, after anything but ordinary instructions and calls, and
+ *  wherever an instruction does not follow straight on from the last.
+ * Instructions without a class which branch are taken to be
+ *  conditional branches. Every item of data is a block of its own,
+ *  which control never goes to or from
+ */
+pub fn blocks<W: Word>(instrs: &[Instr<W>], entries: &[u64]) -> Vec<Block> {
+    let mut leaders: BTreeSet<u64>=entries.iter().copied().collect();
+    for ins in instrs {
+        leaders.extend(ins.ops().filter_map(|op| op.target));
+        if ins.data.is_some() {leaders.insert(ins.addr);}
+        if !carries_on(ins) {leaders.insert(ins.addr+ins.len);}
+    }
+
+    let mut ret: Vec<Block>=Vec::new();
+    for (i,ins) in instrs.iter().enumerate() {
+        let prev=i.checked_sub(1).map(|k| &instrs[k]);
+        if prev.is_none_or(|p| p.addr+p.len!=ins.addr) || leaders.contains(&ins.addr) {
+            ret.push(Block {start: i, end: i, succs: Vec::new()});
+        }
+        ret.last_mut().unwrap().end=i+1;
+    }
+
+    for b in &mut ret {
+        let last=&instrs[b.end-1];
+        // the next instruction, if it follows straight on
+        let next=instrs.get(b.end)
+            .filter(|n| n.data.is_none())
+            .map(|n| n.addr)
+            .filter(|a| *a==last.addr+last.len);
+        let targets=last.ops().filter_map(|op| op.target);
+
+        match last.class() {
+            _ if last.data.is_some() => (),
+            Some(Class::Return) | Some(Class::Halt) => (),
+            Some(Class::Jump) => b.succs.extend(targets.map(|t| (t,Edge::Taken))),
+            _ if !carries_on(last) => {
+                b.succs.extend(targets.map(|t| (t,Edge::Taken)));
+                b.succs.extend(next.map(|n| (n,Edge::NotTaken)));
+            },
+            _ => b.succs.extend(next.map(|n| (n,Edge::FallThrough))),
+        }
+    }
+    ret
+}
+
+/*
+ * Text for a DOT string: quotes and backslashes escaped, and every line
+ *  left aligned
+ */
+fn dot_escape(text: &str) -> String {
+    let mut ret=String::with_capacity(text.len());
+    for c in text.chars() { match c {
+        '"' | '\\' => {ret.push('\\'); ret.push(c);},
+        '\n' => ret.push_str("\\l"),
+        c => ret.push(c),
+    }}
+    ret
+}
+
+/*
+ * Write the blocks <blocks> of <instrs> to <out> as a DOT digraph, each
+ *  node listing its instructions. If <entries> is not empty only the
+ *  blocks reachable from them are written
+ */
+pub fn write_dot<W: Word, O: io::Write>(instrs: &[Instr<W>], blocks: &[Block], entries: &[u64], opts: &RenderOpts, out: &mut O)
+-> io::Result<()> {
+    let by_addr: BTreeMap<u64,usize>=blocks.iter().enumerate()
+        .map(|(k,b)| (instrs[b.start].addr,k))
+        .collect();
+
+    // blocks to write
+    let mut keep=vec![entries.is_empty(); blocks.len()];
+    let mut todo: VecDeque<usize>=entries.iter().filter_map(|e| by_addr.get(e).copied()).collect();
+    while let Some(k)=todo.pop_front() {
+        if keep[k] {continue}
+        keep[k]=true;
+        todo.extend(blocks[k].succs.iter().filter_map(|(a,_)| by_addr.get(a).copied()));
+    }
+
+    writeln!(out,"digraph cfg {{")?;
+    writeln!(out,"    node [shape=box, fontname=\"monospace\"];")?;
+    for (b,_) in blocks.iter().zip(&keep).filter(|(_,k)| **k) {
+        let first=&instrs[b.start];
+        let mut text=String::new();
+        opts.symbols.write_label(first.addr,&mut text);
+        text.push_str(":\n");
+        render(&instrs[b.start..b.end],&BranchTree::new(),opts,Some(first.mode),&mut text);
+        writeln!(out,"    n{:x} [label=\"{}\"];",first.addr,dot_escape(&text))?;
+
+        for (to,edge) in &b.succs {
+            if !by_addr.contains_key(to) {continue}
+            writeln!(out,"    n{:x} -> n{:x} [{}];",first.addr,to,match edge {
+                Edge::FallThrough => "style=dashed",
+                Edge::Taken => "label=\"taken\"",
+                Edge::NotTaken => "label=\"not taken\"",
+            })?;
+        }
+    }
+    writeln!(out,"}}")?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{
+        self,
+        deassemble::branch::ir::{self, ModeMap, DataMap},
+        instrset::{Instrset, binreader::Binreader},
+    };
+
+    // branches to the address in their low bits
+    const FLOW: &str="1 byte words\nmask 0xf0 {\n0x0 = nop\n0x1 = jmp sbranch 0:3 class jump\n\
+        0x2 = beq sbranch 0:3\n0x3 = call sbranch 0:3 class call\n0x4 = ret class return\n0x5 = halt class halt\n}\n";
+
+    // first and last address of a block, and where it goes
+    type Span=(u64,u64,Vec<(u64,Edge)>);
+
+    fn script(text: &str) -> Instrset<u64> {
+        match parse::parse_file(text.as_bytes()) {
+            Ok(is) => is,
+            Err((why,ln)) => panic!("line {}: {}",ln,why),
+        }
+    }
+
+    fn reader(is: &Instrset<u64>, bytes: &[u8]) -> Binreader<u64> {
+        match Binreader::from_reader(bytes,&is.address_layout()) {
+            Ok(br) => br,
+            Err(why) => panic!("{}",why),
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let is=script(FLOW);
+        let br=reader(&is,&[0x00,0x25,0x00,0x37,0x16,0x00,0x40,0x00,0x50]);
+        let (code,fail)=ir::decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        assert!(fail.is_none(),"Failed");
+
+        let got: Vec<Span>=blocks(&code,&[0]).into_iter()
+            .map(|b| (code[b.start].addr,code[b.end-1].addr,b.succs))
+            .collect();
+        let want=[
+            // a conditional branch goes both ways
+            (0,1,vec![(5,Edge::Taken),(2,Edge::NotTaken)]),
+            // calls carry on, and jumps only go to their target
+            (2,4,vec![(6,Edge::Taken)]),
+            (5,5,vec![(6,Edge::FallThrough)]),
+            // nothing follows a return or a halt
+            (6,6,vec![]),
+            (7,8,vec![]),
+        ];
+        assert!(got==want,"Actual: {:?}",got.iter().map(|(s,e,_)| (s,e)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_gap() {
+        // code reached from an entry blocks() is not given, after data
+        let is=script(FLOW);
+        let br=reader(&is,&[0x40,0xff,0xff,0x00,0x50]);
+        let (code,errs)=ir::decode_reached(&br,&is,&ModeMap::new(),&DataMap::new(),&[0,3]);
+        assert!(errs.is_empty(),"Failed");
+
+        let got: Vec<Span>=blocks(&code,&[0]).into_iter()
+            .map(|b| (code[b.start].addr,code[b.end-1].addr,b.succs))
+            .collect();
+        assert!(got==[(0,0,vec![]),(3,4,vec![])],"Actual: {:?}",got.iter().map(|(s,e,_)| (s,e)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,339 @@
+/*
+ * cli.rs - command line arguments
+ */
+use std::{
+    fmt::Display,
+    thread,
+};
+
+use crate::deassemble::Format;
+
+pub const USAGE: &str = "\
+Usage: asm <command> [options] <script> [arguments]
+       asm [options] <script> <binary>      (same as disasm)
+
+Commands:
+  disasm <script> <binary>    Deassemble <binary> (- reads stdin)
+  cfg <script> <binary>       Write the control flow graph of <binary> as
+                              Graphviz DOT
+  xrefs <script> <binary>     List every address referred to in <binary>,
+                              and the instructions referring to it
+  decode <script> <word>...   Decode single words, given as numbers
+  lint <script>               Check a script for likely mistakes
+  info <script> [binary]      Describe a script, and optionally a binary
+  dump-tree <script>          Print the opcode tree of a script
+
+Options:
+  -o, --output <file>   Write output to <file> instead of stdout
+  -f, --format <fmt>    Output format: asm (default) or listing
+  -j, --jobs <n>        Number of threads to use (default: one per CPU)
+  -m, --mode <mode>     Decode in mode <mode> of the script; as
+                        <start>:<end>=<mode> only addresses <start> up
+                        to <end> (may be repeated)
+  -a, --annotate <file> Read address ranges from <file>, with lines like
+                        \"0x100:0x200 mode thumb\" or
+                        \"0x200:0x240 data word\" (data may be byte,
+                        half, word, ascii or skip)
+  -s, --symbols <file>  Name labels and addresses from an nm listing or
+                        linker map, with byte addresses (may be repeated)
+  -d, --describe        Follow each instruction with its description from
+                        the script, as a comment
+      --strict          Treat words whose reserved bits are wrong as
+                        unknown instructions, instead of flagging them
+      --no-aliases      Print every instruction by its own name, not by
+                        the aliases in the script
+  -r, --recursive       Only decode code reached from the entry points,
+                        following branches, and print the rest as data
+      --min-string <n>  Print runs of at least <n> printable characters in
+                        data as text (default: 4; 0 never does). Only
+                        data from --recursive or data ranges is searched:
+                        decoding every word stops at the first one which
+                        is not an instruction, text or not
+  -e, --entry <addr>    Entry point for --recursive and start of the
+                        control flow graph: a number or symbol name
+                        (may be repeated). --recursive also starts at
+                        every code symbol, or at 0 if there are no
+                        entries
+      --functions       Group the output by function, each function
+                        starting at a call target, code symbol or entry
+                        point, and list the functions found
+      --coverage        After decoding, list how often each instruction
+                        of the script matched, the ones never matched
+                        and the words no instruction matched. Decoding
+                        goes on past unknown words, so every one is
+                        counted; with --recursive, only the ones reached
+      --export-symbols <file>
+                        Write every label found to <file>, in a form
+                        --symbols reads back
+  -h, --help            Show this help
+
+Addresses:
+  Addresses given to --mode and --entry, in --annotate files and in
+  script data lines are those of labels: word numbers, or byte offsets if
+  the script has several modes. Relative branches count words, whatever
+  their size.
+  Symbol files and address operands use byte addresses.
+
+Exit status:
+  0  success
+  1  bad command line
+  2  script could not be parsed (or lint found problems)
+  3  I/O error
+  4  binary could not be decoded
+";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    Disasm,
+    Cfg,
+    Xrefs,
+    Decode,
+    Lint,
+    Info,
+    DumpTree,
+    Help,
+}
+
+pub struct Options {
+    pub cmd: Command,
+    pub script: String,
+    pub args: Vec<String>, // binary file, or words for decode
+    pub output: Option<String>,
+    pub format: Format,
+    pub jobs: usize,
+    pub modes: Vec<String>, // --mode arguments, in order
+    pub annotate: Option<String>,
+    pub symbols: Vec<String>, // symbol files
+    pub export_symbols: Option<String>,
+    pub entries: Vec<String>, // --entry arguments
+    pub recursive: bool,
+    pub functions: bool,
+    pub min_string: usize, // shortest text found in data, 0 for none
+    pub describe: bool,
+    pub no_aliases: bool,
+    pub strict: bool,
+    pub coverage: bool,
+}
+
+/*
+ * Ways a run can fail, each with its own exit status
+ */
+pub enum Failure {
+    Usage,
+    Script,
+    IO,
+    Decode,
+}
+impl Failure {
+    pub fn code(&self) -> i32 {
+        match self {
+            Failure::Usage => 1,
+            Failure::Script => 2,
+            Failure::IO => 3,
+            Failure::Decode => 4,
+        }
+    }
+}
+
+pub struct UsageErr(String);
+impl Display for UsageErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f,"{}\nTry --help for usage.",self.0)
+    }
+}
+
+/*
+ * Read options from <args>, not including the program name
+ */
+pub fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Options,UsageErr> {
+    let mut cmd: Option<Command>=None;
+    let mut positional: Vec<String>=Vec::new();
+    let mut opts=Options {
+        cmd: Command::Disasm,
+        script: String::new(),
+        args: Vec::new(),
+        output: None,
+        format: Format::Asm,
+        jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+        modes: Vec::new(),
+        annotate: None,
+        symbols: Vec::new(),
+        export_symbols: None,
+        entries: Vec::new(),
+        recursive: false,
+        functions: false,
+        min_string: 4,
+        describe: false,
+        no_aliases: false,
+        strict: false,
+        coverage: false,
+    };
+
+    let mut only_positional=false;
+    while let Some(arg)=args.next() {
+        if only_positional || arg=="-" || !arg.starts_with('-') {
+            // first positional argument may name a command
+            if cmd.is_none() && positional.is_empty() {
+                cmd=match arg.as_str() {
+                    "disasm" => Some(Command::Disasm),
+                    "cfg" => Some(Command::Cfg),
+                    "xrefs" => Some(Command::Xrefs),
+                    "decode" => Some(Command::Decode),
+                    "lint" => Some(Command::Lint),
+                    "info" => Some(Command::Info),
+                    "dump-tree" => Some(Command::DumpTree),
+                    "help" => Some(Command::Help),
+                    _other => None,
+                };
+                if cmd.is_some() {continue}
+            }
+            positional.push(arg);
+            continue
+        }
+        if arg=="--" {only_positional=true; continue}
+
+        // --name=value or --name value
+        let (name,mut value)=match arg.split_once('=') {
+            Some((n,v)) if arg.starts_with("--") => (n.to_string(),Some(v.to_string())),
+            _other => (arg.clone(),None),
+        };
+        let mut value=|| -> Result<String,UsageErr> {
+            match value.take().or_else(|| args.next()) {
+                Some(v) => Ok(v),
+                None => Err(UsageErr(format!("{} needs a value",name))),
+            }
+        };
+
+        match name.as_str() {
+            "-o" | "--output" => {opts.output=Some(value()?);},
+            "-f" | "--format" => {
+                let v=value()?;
+                opts.format=match v.as_str() {
+                    "asm" => Format::Asm,
+                    "listing" => Format::Listing,
+                    _other => return Err(UsageErr(format!("Unknown format \"{}\"",v))),
+                };
+            },
+            "-j" | "--jobs" => {
+                let v=value()?;
+                opts.jobs=match v.parse::<usize>() {
+                    Ok(n) if n>0 => n,
+                    _other => return Err(UsageErr(format!("Bad number of jobs \"{}\"",v))),
+                };
+            },
+            "-m" | "--mode" => {opts.modes.push(value()?);},
+            "-a" | "--annotate" => {opts.annotate=Some(value()?);},
+            "-s" | "--symbols" => {opts.symbols.push(value()?);},
+            "-d" | "--describe" => {opts.describe=true;},
+            "--strict" => {opts.strict=true;},
+            "--coverage" => {opts.coverage=true;},
+            "--no-aliases" => {opts.no_aliases=true;},
+            "-r" | "--recursive" => {opts.recursive=true;},
+            "-e" | "--entry" => {opts.entries.push(value()?);},
+            "--min-string" => {
+                let v=value()?;
+                opts.min_string=match v.parse::<usize>() {
+                    Ok(n) => n,
+                    _other => return Err(UsageErr(format!("Bad string length \"{}\"",v))),
+                };
+            },
+            "--functions" => {opts.functions=true;},
+            "--export-symbols" => {opts.export_symbols=Some(value()?);},
+            "-h" | "--help" => {opts.cmd=Command::Help; return Ok(opts)},
+            other => return Err(UsageErr(format!("Unknown option \"{}\"",other))),
+        }
+    }
+
+    opts.cmd=cmd.unwrap_or(Command::Disasm);
+    if opts.cmd==Command::Help {return Ok(opts)}
+
+    // check number of arguments
+    let mut positional=positional.into_iter();
+    opts.script=match positional.next() {
+        Some(s) => s,
+        None => return Err(UsageErr("No script given".to_string())),
+    };
+    opts.args=positional.collect();
+    let (min,max)=match opts.cmd {
+        Command::Disasm | Command::Cfg | Command::Xrefs => (1,1),
+        Command::Decode => (1,usize::MAX),
+        Command::Info => (0,1),
+        _other => (0,0),
+    };
+    if opts.args.len()<min {
+        return Err(UsageErr(match opts.cmd {
+            Command::Decode => "No words given to decode".to_string(),
+            _other => "No binary file given".to_string(),
+        }))
+    }
+    if opts.args.len()>max {
+        return Err(UsageErr(format!("Unexpected argument \"{}\"",opts.args[max])))
+    }
+
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options,UsageErr> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn err(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} parsed",args),
+            Err(UsageErr(why)) => why,
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        for args in [&["-o","out.s","-j","3","isa.txt","a.bin"][..], &["--output=out.s","--jobs=3","isa.txt","a.bin"], &["isa.txt","--output","out.s","a.bin","--jobs","3"]] {
+            let Ok(o)=parse(args) else {panic!("{:?} failed",args)};
+            assert!(o.output.as_deref()==Some("out.s") && o.jobs==3,"Actual: {:?} {}",o.output,o.jobs);
+            assert!(o.script=="isa.txt" && o.args==["a.bin"],"Actual: {} {:?}",o.script,o.args);
+        }
+        let why=err(&["isa.txt","a.bin","-o"]);
+        assert!(why=="-o needs a value","Actual: {}",why);
+        let why=err(&["--jobs=0","isa.txt","a.bin"]);
+        assert!(why=="Bad number of jobs \"0\"","Actual: {}",why);
+    }
+
+    #[test]
+    fn test_positional() {
+        // - is stdin, and everything after -- is positional
+        let Ok(o)=parse(&["isa.txt","-"]) else {panic!("- failed")};
+        assert!(o.args==["-"],"Actual: {:?}",o.args);
+        let Ok(o)=parse(&["isa.txt","--","-o"]) else {panic!("-- failed")};
+        assert!(o.args==["-o"] && o.output.is_none(),"Actual: {:?}",o.args);
+        let why=err(&["isa.txt","-x"]);
+        assert!(why=="Unknown option \"-x\"","Actual: {}",why);
+    }
+
+    #[test]
+    fn test_commands() {
+        // the old form without a command disassembles
+        let Ok(o)=parse(&["isa.txt","a.bin"]) else {panic!("legacy form failed")};
+        assert!(o.cmd==Command::Disasm && o.script=="isa.txt" && o.args==["a.bin"]);
+
+        let Ok(o)=parse(&["decode","isa.txt","0x12","7"]) else {panic!("decode failed")};
+        assert!(o.cmd==Command::Decode && o.args==["0x12","7"],"Actual: {:?}",o.args);
+        for args in [&["info","isa.txt"][..], &["info","isa.txt","a.bin"], &["lint","isa.txt"], &["help"], &["-h","lint"]] {
+            assert!(parse(args).is_ok(),"{:?} failed",args);
+        }
+
+        for (args,want) in [
+            (&["disasm","isa.txt"][..], "No binary file given"),
+            (&["decode","isa.txt"], "No words given to decode"),
+            (&["lint"], "No script given"),
+            (&["lint","isa.txt","a.bin"], "Unexpected argument \"a.bin\""),
+            (&["info","isa.txt","a.bin","b.bin"], "Unexpected argument \"b.bin\""),
+            (&["isa.txt","a.bin","b.bin"], "Unexpected argument \"b.bin\""),
+        ] {
+            let why=err(args);
+            assert!(why==want,"{:?}: {}",args,why);
+        }
+    }
+}
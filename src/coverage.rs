@@ -0,0 +1,154 @@
+/*
+ * coverage.rs - how much of an instructions set a binary uses
+ */
+use std::{
+    io,
+    collections::BTreeMap,
+};
+
+use super::{
+    Instr,
+    bits::Word,
+    branch::ir::{DecodeErrType, ModeErrs},
+    instrset::{Instrset, Maskmap, Node},
+};
+
+/*
+ * An instruction of the opcode tree: its name, the mode or table it is
+ *  in, its opcode (every key on the way to it ORed together), its
+ *  Instrfmt.id and how many decoded instructions it matched
+ */
+pub struct Entry<'a,W: Word> {
+    pub name: &'a str,
+    pub table: &'a str,
+    pub opcode: W,
+    pub id: usize,
+    pub hits: usize,
+}
+
+/*
+ * Every instruction of <is>, each mode and then each table in order, and
+ *  the instructions of each in opcode order
+ */
+pub fn entries<W: Word>(is: &Instrset<W>) -> Vec<Entry<'_,W>> {
+    let mut ret=Vec::new();
+    for m in &is.modes {
+        walk(&m.set,&m.name,W::ZERO,&mut ret);
+    }
+    for name in is.table_names() {
+        walk(&is.tables[name],name,W::ZERO,&mut ret);
+    }
+    ret
+}
+
+/*
+ * Helper for entries()
+ * <path> is the opcode of <set> (all parent opcodes ORed together)
+ */
+fn walk<'a,W: Word>(set: &'a Maskmap<W>, table: &'a str, path: W, out: &mut Vec<Entry<'a,W>>) {
+    let mut keys: Vec<&W>=set.map.keys().collect();
+    keys.sort();
+    for k in keys { match &set.map[k] {
+        Node::Map(m) => walk(m,table,path|*k,out),
+        Node::Instr((name,ifmt)) => out.push(Entry {name, table, opcode: path|*k, id: ifmt.id, hits: 0}),
+        Node::Prefix(_) => (),
+    }}
+}
+
+/*
+ * Count the instructions of <instrs> each of <entries> matched, by
+ *  Instrfmt.id, so that instructions with the same name are told apart
+ */
+pub fn count<W: Word>(entries: &mut [Entry<'_,W>], instrs: &[Instr<'_,W>]) {
+    let mut hits: Vec<usize>=vec![0; entries.iter().map(|e| e.id+1).max().unwrap_or(0)];
+    for entry in instrs.iter().filter_map(|ins| ins.entry) {
+        hits[entry.1.id]+=1;
+    }
+    for e in entries.iter_mut() {
+        e.hits=hits[e.id];
+    }
+}
+
+/*
+ * Words no instruction matched in <errs>, by mode and the bits of the
+ *  word under the top-level mask of the mode: how many, and the address
+ *  of the first
+ */
+pub fn unknown_opcodes<W: Word>(is: &Instrset<W>, errs: &ModeErrs<W>) -> BTreeMap<(usize,W),(usize,u64)> {
+    let mut ret: BTreeMap<(usize,W),(usize,u64)>=BTreeMap::new();
+    for (mode,why) in errs {
+        if !matches!(why.typ,DecodeErrType::UnknownOp | DecodeErrType::Reserved(_)) {continue}
+        let e=ret.entry((*mode,why.word & is.modes[*mode].set.mask)).or_insert((0,why.addr));
+        e.0+=1;
+    }
+    ret
+}
+
+/*
+ * Write a coverage report of <is> to <out>: how many of <instrs> each
+ *  instruction matched, most first, the instructions never matched, and
+ *  the unknown opcodes of <errs>, most common first
+ */
+pub fn write_report<W: Word, O: io::Write>(is: &Instrset<W>, instrs: &[Instr<'_,W>], errs: &ModeErrs<W>, out: &mut O)
+-> io::Result<()> {
+    let mut all=entries(is);
+    count(&mut all,instrs);
+    let (mut hit,missed): (Vec<&Entry<W>>,Vec<&Entry<W>>)=all.iter().partition(|e| e.hits>0);
+    hit.sort_by_key(|e| std::cmp::Reverse(e.hits));
+
+    let w=all.iter().map(|e| e.name.len()).max().unwrap_or(0).max("Instruction".len());
+    let t=all.iter().map(|e| e.table.len()).max().unwrap_or(0).max("Table".len());
+    writeln!(out,"{:w$}  {:t$}  {:>18}  {:>10}","Instruction","Table","Opcode","Hits")?;
+    for e in &hit {
+        writeln!(out,"{:w$}  {:t$}  {:>#18x}  {:>10}",e.name,e.table,e.opcode,e.hits)?;
+    }
+
+    writeln!(out,"Never matched: {} of {} instructions",missed.len(),all.len())?;
+    for e in &missed {
+        writeln!(out,"    {:w$}  {:t$}  {:#x}",e.name,e.table,e.opcode)?;
+    }
+
+    let unknown=unknown_opcodes(is,errs);
+    let mut common: Vec<_>=unknown.iter().collect();
+    common.sort_by_key(|(_,(n,_))| std::cmp::Reverse(*n));
+    writeln!(out,"Unknown opcodes: {} words",common.iter().map(|(_,(n,_))| n).sum::<usize>())?;
+    for ((mode,bits),(n,first)) in common {
+        match is.modes.len() {
+            1 => writeln!(out,"    {:>#18x}  {:>10}  first at {:#x}",bits,n,first)?,
+            _ => writeln!(out,"    {:>#18x}  {:>10}  first at {:#x} ({})",bits,n,first,is.modes[*mode].name)?,
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{
+        self,
+        deassemble::branch::ir::{self, ModeMap, DataMap},
+        instrset::binreader::Binreader,
+    };
+
+    #[test]
+    fn test_count() {
+        // two instructions named ld, told apart by where they are
+        let is: Instrset<u64>=match parse::parse_file(&b"1 byte words\nmask 0xf0 {\n0x0 = ld\n0x1 prefix t\n}\n\
+            table t mask 0xf0 {\n0x0 = ld\n0x2 = st\n}\n"[..]) {
+            Ok(is) => is,
+            Err((why,ln)) => panic!("line {}: {}",ln,why),
+        };
+        let br=match Binreader::from_reader(&[0x00,0x10,0x00,0x00,0xf0,0xf1,0xe0][..],&is.address_layout()) {
+            Ok(br) => br,
+            Err(why) => panic!("{}",why),
+        };
+        let (code,errs)=ir::decode_sweep(&br,&is,&ModeMap::new(),&DataMap::new());
+        let mut all=entries(&is);
+        count(&mut all,&code);
+        let hits: Vec<(&str,&str,usize)>=all.iter().map(|e| (e.name,e.table,e.hits)).collect();
+        assert!(hits==[("ld","main",2),("ld","t",1),("st","t",0)],"Actual: {:?}",hits);
+
+        let unknown: Vec<(u64,(usize,u64))>=unknown_opcodes(&is,&errs).into_iter().map(|((_,w),v)| (w,v)).collect();
+        assert!(unknown==[(0xe0,(1,6)),(0xf0,(2,4))],"Actual: {:?}",unknown);
+    }
+}
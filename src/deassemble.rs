@@ -1,136 +1,262 @@
-use std::fmt::Display;
+use std::{
+    io,
+    fmt::Write,
+};
 
 #[path="branch.rs"]
 pub mod branch;
+
+#[path="cfg.rs"]
+pub mod cfg;
+
+#[path="funcs.rs"]
+pub mod funcs;
+
+#[path="coverage.rs"]
+pub mod coverage;
 pub use branch::{
+    ir::Instr,
+    symbols::Symbols,
     instrset as instrset, instrset::{
-        Instrset,
-        FmtType,
-        binreader::Binreader,
-        bits as bits, bits::{
-            Wordt,
-            minimize,
-        },
+        FmtType, DataKind,
+        binreader::{self as binreader, CHUNK_WORDS},
+        bits as bits, bits::Word,
     }
 };
 
-enum DeasmErrType {
-    UnknownOp(Wordt),
-    Binread(instrset::binreader::BinReaderErr),
+/*
+ * Output formats
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Asm,     // plain assembly
+    Listing, // assembly with the address and raw word of every instruction
 }
-pub struct DeasmErr {
-    typ: DeasmErrType,
-    words_read: Wordt,
+
+/*
+ * Settings for turning decoded instructions into text
+ */
+pub struct RenderOpts {
+    pub format: Format,
+    pub modes: Vec<(String,usize)>, // name and word bits of every mode
+    pub data_bits: usize,           // bits in each word of data
+    pub symbols: Symbols,           // names for labels and addresses
+    pub xrefs: branch::Xrefs,       // references listed after each label
+    pub describe: bool,             // descriptions as comments after instructions
+    pub aliases: bool,              // print instructions by their aliases where they apply
 }
-impl Display for DeasmErr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
-        match &self.typ {
-            DeasmErrType::UnknownOp(w) =>
-                write!(f, "[At {:#x}] Unknown instruction: {:#x}",self.words_read,w),
-            DeasmErrType::Binread(why) =>
-                write!(f,"[At {:#x}] I/O error: {}",self.words_read,why),
+
+/*
+ * Append <text> to <out> as it goes in a quoted string: characters
+ *  encoded in UTF-8 as they are, and bytes which are not in escapes
+ */
+fn escape_text(text: &[u8], out: &mut String) {
+    for chunk in text.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c.is_ascii() {
+                true  => escape_char(c as u8,out),
+                false if c.is_control() => {
+                    let mut b=[0u8; 4];
+                    for x in c.encode_utf8(&mut b).bytes() {escape_char(x,out);}
+                },
+                false => out.push(c),
+            }
         }
+        for b in chunk.invalid() {escape_char(*b,out);}
     }
 }
 
-fn deassemble_instr(w: Wordt, is: &Instrset, tree: &mut branch::BranchTree, i: &u64) -> Result<(),DeasmErr> {
-    let mut mask_total: bits::Bitmask = 0;
-    let mut d: (Wordt,Wordt); // data under current Fmt mask
+/*
+ * Append byte <b> to <out> as it goes in a quoted string
+ */
+fn escape_char(b: u8, out: &mut String) {
+    // writing to a String cannot fail
+    let _=match b {
+        b'"' => write!(out,"\\\""),
+        b'\\' => write!(out,"\\\\"),
+        b'\n' => write!(out,"\\n"),
+        b'\t' => write!(out,"\\t"),
+        b'\r' => write!(out,"\\r"),
+        b' '..=b'~' => write!(out,"{}",b as char),
+        b => write!(out,"\\x{:02x}",b),
+    };
+}
 
-    match instrset::get_fmt(w,&is.set,&mut mask_total) {
-        None => {
-            return Err(DeasmErr {
-                typ: DeasmErrType::UnknownOp(w),
-                words_read: 0, // will be set by deassemble_file
-            })
-        },
-        Some((name,ifmt)) => {
-            // TODO lock stdout during loop
-            // print instruction
-            print!("{}",name);
-            for f in &ifmt.fmt {
-                // Apply BitOps
-                d=minimize(w,f.mask);
-                bits::apply_bit_ops(f.ops.iter(),&mut d.0);
-
-                match &f.typ {
-                    FmtType::Addr => {
-                        print!(" {:#x}",d.0);},
-                    FmtType::Unsigned => {
-                        print!( " {}",d.0);},
-                    FmtType::Signed   => {
-                        print!( " {}",bits::twoscomp(d));
-                    },
-                    FmtType::Binary => {
-                        print!( " {:#b}",d.0);
-                    },
-
-                    FmtType::Ubranch => {print!(" label_{:#x}",(*i)-d.0);},
-                    FmtType::Dbranch => {
-                        print!(" label_{:#x}",(*i)+d.0);
-                        tree.insert((*i)+d.0);
-                    },
-                    FmtType::Ibranch => {
-                        if d.0>0 {
-                            print!(" label_{:#x}",(*i)+d.0);
-                            tree.insert(( *i)+d.0 );
-                        }
-                        else {
-                            print!(" label_{:#x}",
-                                (*i)-Wordt::from_le_bytes((-1*bits::twoscomp(d)).to_le_bytes())
-                            )
-                        }
-                    },
-                    FmtType::Sbranch => {
-                        print!(" label_{:#x}",d.0);
-                        if d.0>=(*i) {tree.insert(d.0);}
-                    },
-
-                    FmtType::Ignore => (),
-                }
-                mask_total |= f.mask;
+/*
+ * Append the text for decoded instruction <ins> to <out>
+ */
+fn render_instr<W: Word>(ins: &Instr<W>, opts: &RenderOpts, out: &mut String) {
+    // writing to a String cannot fail
+    let bits=match ins.data {
+        None => opts.modes[ins.mode].1,
+        Some(DataKind::Units) => opts.data_bits,
+        Some(DataKind::Half) => 16,
+        Some(DataKind::Word) => 32,
+        Some(_) => 8,
+    };
+    if opts.format==Format::Listing {
+        let _=write!(out,"{:8x}: ",ins.addr);
+        if ins.data!=Some(DataKind::Skip) {
+            for w in ins.words() {
+                let _=write!(out," {:0w$x}",w,w=bits.div_ceil(4));
             }
-            // default formatter for instruction parts without format provided
-            // Use it if mask_total is less than
-            //  maximum possible word of size <is.wordsize>,
-            //  i.e. 0b11111111 for 1 byte wordsize
-            if mask_total < !0&((1<<is.wordsize*8)-1) {
-                print!(" {:#x}",minimize(w,!mask_total).0);
-            }
-            println!();
-            return Ok(())
         }
+        out.push_str("  ");
     }
-}
+    if let Some(kind)=ins.data {
+        out.push_str(ins.name());
+        match kind {
+            DataKind::Skip => {let _=write!(out," {}",ins.word);},
+            DataKind::Ascii | DataKind::Asciz => {
+                let mut text: Vec<u8>=ins.words().map(|w| w.to_u64() as u8).collect();
+                if kind==DataKind::Asciz {text.pop();}
+                out.push_str(" \"");
+                escape_text(&text,out);
+                out.push('"');
+            },
+            _other => for (k,w) in ins.words().enumerate() {
+                let _=write!(out,"{}{:#0w$x}",match k {0 => " ", _ => ", "},w,w=2+bits.div_ceil(4));
+            },
+        }
+        out.push('\n');
+        return
+    }
+    for (_,name) in ins.prefixes() {
+        if let Some(name)=name {
+            out.push_str(name);
+            out.push(' ');
+        }
+    }
+    let alias=match opts.aliases {
+        true  => ins.alias(),
+        false => None,
+    };
+    let ops: Vec<_>=ins.ops().collect();
+    let shown: Vec<_>=match alias {
+        Some(a) => a.ops.iter().map(|k| &ops[*k]).collect(),
+        None => ops.iter().collect(),
+    };
+    out.push_str(alias.map_or(ins.name(),|a| a.name.as_str()));
+    for op in shown {
+        let d=op.val;
+        let _=match &op.fmt.typ {
+            FmtType::Addr => {
+                out.push(' ');
+                opts.symbols.write_addr(d.0,out);
+                Ok(())
+            },
+            FmtType::Unsigned => write!(out," {}",d.0),
+            FmtType::Signed   => write!(out," {}",bits::twoscomp(d)),
+            FmtType::Binary => write!(out," {:#b}",d.0),
 
-pub fn deassemble_file(br: &mut Binreader, is: &Instrset, tree: &mut branch::BranchTree) -> Result<(),DeasmErr>{
-    // read every instruction
-    for i in 0..br.n_instrs {
-        // check to generate labels
-        match tree.first() {
-            Some(n) => {
-                if i==*n {
-                    println!("label_{:#x}:",i);
-                    tree.pop_first();
+            FmtType::Ubranch | FmtType::Dbranch |
+            FmtType::Ibranch | FmtType::Sbranch => {
+                if let Some(t)=op.target {
+                    out.push(' ');
+                    opts.symbols.write_label(t,out);
                 }
+                Ok(())
             },
-            None => (),
+
+            FmtType::Ignore => Ok(()),
+        };
+    }
+    // default formatter for instruction parts without format provided;
+    //  aliases leave it out if it is 0
+    match ins.rest {
+        Some(rest) if alias.is_none() || rest!=W::ZERO => {let _=write!(out," {:#x}",rest);},
+        _other => (),
+    }
+    let reserved=ins.reserved();
+    if reserved!=W::ZERO {
+        let _=write!(out," ; reserved bits {:#x} are wrong",reserved);
+    }
+    if let (true,Some(desc))=(opts.describe,ins.desc()) {
+        let _=write!(out," ; {}",desc);
+    }
+    out.push('\n');
+}
+
+/*
+ * Render decoded instructions <instrs> into <out>, with a label line
+ *  before every instruction in <tree>, listing where it is referred
+ *  to from.
+ * If there are several modes, a directive like ".thumb" goes before
+ *  every instruction in a different mode from the one before it (data
+ *  has no mode); <prev> is the mode of the last instruction before
+ *  <instrs>, if any.
+ */
+pub fn render<W: Word>(instrs: &[Instr<W>], tree: &branch::BranchTree, opts: &RenderOpts, mut prev: Option<usize>, out: &mut String) {
+    for ins in instrs {
+        if ins.data.is_none() {
+            if opts.modes.len()>1 && prev!=Some(ins.mode) {
+                let _=writeln!(out,".{}",opts.modes[ins.mode].0);
+            }
+            prev=Some(ins.mode);
         }
+        if tree.contains(&ins.addr) {
+            opts.symbols.write_label(ins.addr,out);
+            out.push(':');
+            for (k,r) in opts.xrefs.get(ins.addr).iter().enumerate() {
+                let _=write!(out,"{}{:#x}",match k {0 => " ; from ", _ => ", "},r.from);
+            }
+            out.push('\n');
+        }
+        render_instr(ins,opts,out);
+    }
+}
 
-        // deassemble instruction
-        match br.next() {
-            Ok(w) => { match deassemble_instr(w,&is,tree,&i) {
-                Ok(()) => (),
-                Err(mut e) => {
-                    e.words_read=i;
-                    return Err(e)
-                }
-            }},
-            Err(why) => { return Err(DeasmErr {
-                typ: DeasmErrType::Binread(why),
-                words_read: i,
-            })},
-    }}
-
-    Ok(())
+/*
+ * Render decoded instructions <instrs> to <out>, using up to <jobs>
+ *  threads. Output is the same whatever the number of threads.
+ * Stops at the first write error.
+ */
+pub fn render_all<W: Word, O: io::Write>(instrs: &[Instr<W>], tree: &branch::BranchTree, opts: &RenderOpts, jobs: usize, out: &mut O)
+-> io::Result<()> {
+    // each chunk with the mode of the last instruction before it
+    let chunks: Vec<(Option<usize>,&[Instr<W>])>=instrs.chunks(CHUNK_WORDS as usize)
+        .enumerate()
+        .map(|(k,c)| (instrs[..k*CHUNK_WORDS as usize].iter().rev().find(|ins| ins.data.is_none()).map(|ins| ins.mode),c))
+        .collect();
+    let mut res: io::Result<()>=Ok(());
+
+    binreader::par_map(&chunks, jobs,
+        |(prev,chunk)| {
+            let mut s=String::new();
+            render(chunk,tree,opts,*prev,&mut s);
+            s
+        },
+        |s| {
+            res=out.write_all(s.as_bytes());
+            res.is_ok()
+        });
+
+    res?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(text: &[u8]) -> String {
+        let mut out=String::new();
+        escape_text(text,&mut out);
+        out
+    }
+
+    #[test]
+    fn test_escape_ascii() {
+        let result=escaped(b"say \"hi\"\\\n\t\r");
+        assert!(result=="say \\\"hi\\\"\\\\\\n\\t\\r","Actual: {}",result);
+    }
+
+    #[test]
+    fn test_escape_utf8() {
+        // printable characters stay, control characters and bad bytes do not
+        let result=escaped("é€".as_bytes());
+        assert!(result=="é€","Actual: {}",result);
+        let result=escaped(&[b'a',0xc2,0x85,0xff,0x00]);
+        assert!(result=="a\\xc2\\x85\\xff\\x00","Actual: {}",result);
+    }
 }
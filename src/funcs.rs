@@ -0,0 +1,174 @@
+/*
+ * funcs.rs - functions: the basic blocks reachable from each call target
+ */
+use std::{
+    io,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use super::{
+    render,
+    RenderOpts,
+    Instr,
+    bits::Word,
+    branch::BranchTree,
+    cfg::Block,
+    instrset::Class,
+};
+
+/*
+ * A function starting at <entry>, made of blocks <blocks> (indexes into
+ *  the blocks of a binary): the entry block, then the others in address
+ *  order
+ */
+pub struct Function {
+    pub entry: u64,
+    pub blocks: Vec<usize>,
+    pub size: u64,       // addresses taken by its blocks
+    pub n_instrs: usize, // instructions in its blocks
+}
+
+/*
+ * Targets of the branch operands of call instructions in <instrs>
+ */
+pub fn call_targets<W: Word>(instrs: &[Instr<W>]) -> BTreeSet<u64> {
+    instrs.iter()
+        .filter(|ins| ins.class()==Some(Class::Call))
+        .flat_map(|ins| ins.ops().filter_map(|op| op.target))
+        .collect()
+}
+
+/*
+ * Functions of <instrs>, split into <blocks>, starting at <entries>
+ *  (in ascending order).
+ * Each function takes the blocks reachable from its entry without going
+ *  through another entry. A block reachable from several entries
+ *  belongs to the function with the lowest entry.
+ * Entries which do not start a block of code are left out
+ */
+pub fn find<W: Word>(instrs: &[Instr<W>], blocks: &[Block], entries: &[u64]) -> Vec<Function> {
+    let by_addr: BTreeMap<u64,usize>=blocks.iter().enumerate()
+        .filter(|(_,b)| instrs[b.start].data.is_none())
+        .map(|(k,b)| (instrs[b.start].addr,k))
+        .collect();
+    let mut taken=vec![false; blocks.len()];
+
+    let mut ret=Vec::new();
+    for e in entries {
+        let Some(&first)=by_addr.get(e) else {continue};
+        if taken[first] {continue}
+
+        let mut mine=Vec::new();
+        let mut todo=vec![first];
+        taken[first]=true;
+        while let Some(k)=todo.pop() {
+            mine.push(k);
+            for (to,_) in &blocks[k].succs {
+                match by_addr.get(to) {
+                    Some(&n) if !taken[n] && entries.binary_search(to).is_err() => {
+                        taken[n]=true;
+                        todo.push(n);
+                    },
+                    _other => (),
+                }
+            }
+        }
+        mine.sort_by_key(|k| (*k!=first,*k));
+
+        let last=|k: &usize| &instrs[blocks[*k].end-1];
+        ret.push(Function {
+            entry: *e,
+            size: mine.iter().map(|k| last(k).addr+last(k).len-instrs[blocks[*k].start].addr).sum(),
+            n_instrs: mine.iter().map(|k| blocks[*k].end-blocks[*k].start).sum(),
+            blocks: mine,
+        });
+    }
+    ret
+}
+
+/*
+ * Render <instrs> to <out> grouped by function: the blocks of each of
+ *  <funcs> between ".func name" and ".endfunc", and then everything
+ *  outside any function, in address order
+ */
+pub fn render_grouped<W: Word, O: io::Write>(instrs: &[Instr<W>], blocks: &[Block], funcs: &[Function], tree: &BranchTree, opts: &RenderOpts, out: &mut O)
+-> io::Result<()> {
+    let mut prev=None;
+    let mut block=|k: usize, out: &mut O| -> io::Result<()> {
+        let part=&instrs[blocks[k].start..blocks[k].end];
+        let mut s=String::new();
+        render(part,tree,opts,prev,&mut s);
+        prev=part.iter().rev().find(|ins| ins.data.is_none()).map(|ins| ins.mode).or(prev);
+        out.write_all(s.as_bytes())
+    };
+
+    let mut outside=vec![true; blocks.len()];
+    for f in funcs {
+        let mut name=String::new();
+        opts.symbols.write_label(f.entry,&mut name);
+        writeln!(out,".func {}",name)?;
+        for k in &f.blocks {
+            outside[*k]=false;
+            block(*k,out)?;
+        }
+        writeln!(out,".endfunc")?;
+    }
+    for k in (0..blocks.len()).filter(|k| outside[*k]) {
+        block(k,out)?;
+    }
+    out.flush()
+}
+
+/*
+ * Write a table of <funcs> to <out>: name, start address, size and
+ *  number of instructions of each
+ */
+pub fn write_summary<O: io::Write>(funcs: &[Function], opts: &RenderOpts, out: &mut O) -> io::Result<()> {
+    let names: Vec<String>=funcs.iter().map(|f| {
+        let mut name=String::new();
+        opts.symbols.write_label(f.entry,&mut name);
+        name
+    }).collect();
+    let w=names.iter().map(|n| n.len()).max().unwrap_or(0).max("Function".len());
+
+    writeln!(out,"{:w$}  {:>10}  {:>8}  {:>12}","Function","Start","Size","Instructions")?;
+    for (f,name) in funcs.iter().zip(&names) {
+        writeln!(out,"{:w$}  {:>#10x}  {:>8}  {:>12}",name,f.entry,f.size,f.n_instrs)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{
+        self,
+        deassemble::{cfg, branch::ir::{self, ModeMap, DataMap}},
+        instrset::{Instrset, binreader::Binreader},
+    };
+
+    fn script(text: &str) -> Instrset<u64> {
+        match parse::parse_file(text.as_bytes()) {
+            Ok(is) => is,
+            Err((why,ln)) => panic!("line {}: {}",ln,why),
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        // both entries jump to the ret at 4, which the lower one takes
+        let is=script("1 byte words\nmask 0xf0 {\n0x0 = nop\n0x1 = jmp sbranch 0:3 class jump\n0x4 = ret class return\n}\n");
+        let br=match Binreader::from_reader(&[0x14,0x00,0x14,0x00,0x40][..],&is.address_layout()) {
+            Ok(br) => br,
+            Err(why) => panic!("{}",why),
+        };
+        let (code,fail)=ir::decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        assert!(fail.is_none(),"Failed");
+
+        let blocks=cfg::blocks(&code,&[0,2]);
+        let got: Vec<(u64,Vec<u64>,u64,usize)>=find(&code,&blocks,&[0,2]).into_iter()
+            .map(|f| (f.entry,f.blocks.iter().map(|k| code[blocks[*k].start].addr).collect(),f.size,f.n_instrs))
+            .collect();
+        assert!(got==[(0,vec![0,4],2,2),(2,vec![2],1,1)],"Actual: {:?}",got);
+    }
+}
@@ -2,17 +2,19 @@
 * instrset.rs
 * structs for representing an instructions set
 */
-use std::collections::{
-    HashMap,
-    LinkedList,
+use std::{
+    io::{self, Write},
+    collections::{
+        HashMap,
+        LinkedList,
+    },
 };
 
 #[path="binreader.rs"]
 pub mod binreader;
 pub use binreader::{
     bits as bits, bits::{
-        Wordt,
-        Bitmask,
+        Word,
         BitOp,
     },
 };
@@ -34,10 +36,163 @@ pub enum FmtType {
     Ignore,
 }
 
-pub struct Fmt {
+impl FmtType {
+    /*
+     * Format type for a format keyword in a script
+     */
+    pub fn from_keyword(word: &str) -> Option<FmtType> {
+        match word {
+            "addr" => Some(FmtType::Addr),
+            "uint" => Some(FmtType::Unsigned),
+            "int"  => Some(FmtType::Signed),
+            "bin"  => Some(FmtType::Binary),
+
+            "ubranch" => Some(FmtType::Ubranch),
+            "dbranch" => Some(FmtType::Dbranch),
+            "ibranch" => Some(FmtType::Ibranch),
+            "sbranch" => Some(FmtType::Sbranch),
+
+            "ignore" => Some(FmtType::Ignore),
+
+            _other => None,
+        }
+    }
+
+    /*
+     * Script keyword for this format type
+     */
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            FmtType::Addr => "addr",
+            FmtType::Unsigned => "uint",
+            FmtType::Signed => "int",
+            FmtType::Binary => "bin",
+
+            FmtType::Ubranch => "ubranch",
+            FmtType::Dbranch => "dbranch",
+            FmtType::Ibranch => "ibranch",
+            FmtType::Sbranch => "sbranch",
+
+            FmtType::Ignore => "ignore",
+        }
+    }
+}
+
+/*
+ * What an instruction does to the flow of control, if it is more than
+ *  carrying on to the next instruction
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Class {
+    Call,    // branch which returns to the next instruction
+    Jump,    // unconditional branch
+    Cbranch, // conditional branch; may also carry on
+    Return,  // return from a call
+    Halt,    // stops; nothing after it runs
+}
+
+impl Class {
+    /*
+     * Class for a class keyword in a script
+     */
+    pub fn from_keyword(word: &str) -> Option<Class> {
+        match word {
+            "call" => Some(Class::Call),
+            "jump" => Some(Class::Jump),
+            "cbranch" => Some(Class::Cbranch),
+            "return" => Some(Class::Return),
+            "halt" => Some(Class::Halt),
+            _other => None,
+        }
+    }
+
+    /*
+     * Script keyword for this class
+     */
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Class::Call => "call",
+            Class::Jump => "jump",
+            Class::Cbranch => "cbranch",
+            Class::Return => "return",
+            Class::Halt => "halt",
+        }
+    }
+}
+
+/*
+ * How to print a range of a binary which is not code
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum DataKind {
+    Units, // words as the binary is addressed: bytes, or the words of the only mode
+    Byte,  // 8 bit numbers
+    Half,  // 16 bit numbers
+    Word,  // 32 bit numbers
+    Ascii, // text
+    Asciz, // text ending in NUL
+    Skip,  // not printed, only its size
+}
+
+impl DataKind {
+    /*
+     * Kind of data for a keyword in a script or annotation file
+     */
+    pub fn from_keyword(word: &str) -> Option<DataKind> {
+        match word {
+            "byte" => Some(DataKind::Byte),
+            "half" => Some(DataKind::Half),
+            "word" => Some(DataKind::Word),
+            "ascii" => Some(DataKind::Ascii),
+            "skip" => Some(DataKind::Skip),
+            _other => None,
+        }
+    }
+
+    /*
+     * Script keyword for this kind of data
+     */
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            DataKind::Units => "units",
+            DataKind::Byte => "byte",
+            DataKind::Half => "half",
+            DataKind::Word => "word",
+            DataKind::Ascii => "ascii",
+            DataKind::Asciz => "asciz",
+            DataKind::Skip => "skip",
+        }
+    }
+
+    /*
+     * Bytes in each item, or None if items are whole addresses (text and
+     *  skipped data can be any number of addresses)
+     */
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            DataKind::Units | DataKind::Ascii | DataKind::Asciz | DataKind::Skip => None,
+            DataKind::Byte => Some(1),
+            DataKind::Half => Some(2),
+            DataKind::Word => Some(4),
+        }
+    }
+}
+
+/*
+ * Addresses <start> up to (but not including) <end> hold data of <kind>
+ */
+#[derive(Clone)]
+pub struct DataRange {
+    pub start: u64,
+    pub end: u64,
+    pub kind: DataKind,
+}
+
+pub struct Fmt<W: Word> {
     pub typ: FmtType,
-    pub mask: Bitmask,
-    pub ops: LinkedList<BitOp>,
+    pub mask: W,
+    pub words: (usize,usize), // first and last word the mask applies to, joined; 0 is the opcode word
+    pub ops: LinkedList<BitOp<W>>,
 }
 
 /*
@@ -47,44 +202,289 @@ pub struct Fmt {
  *  For example an instruction which adds the value at an address with
  *  a constant and places their sum in a third address might have fmt:
  *   [(Fmt::Addr,0b...), (Fmt::Signed,0b...), (Fmt::Addr,0b...)]
+ * ext is the number of words following the opcode word which belong to
+ *  the instruction, such as immediates or extension words.
+ * class says how the instruction changes the flow of control, if given.
+ * switch is set for instructions which change the mode of the code they
+ *  branch to (or of the code after them, if they do not branch).
+ * desc is a description of the instruction for people reading the
+ *  output, like "Add immediate to register".
+ * aliases are the other names the instruction is printed with when its
+ *  operands allow, most specific first.
+ * reserved is a mask of bits of the opcode word which must have fixed
+ *  values, and those values.
+ * id numbers the instructions of a script in the order they are given,
+ *  from 0, so that decoded instructions can be told apart by where in
+ *  the script they come from.
+ */
+pub struct Instrfmt<W: Word> {
+    pub fmt: Vec<Fmt<W>>,
+    pub ext: usize,
+    pub class: Option<Class>,
+    pub switch: Option<Switch>,
+    pub desc: Option<String>,
+    pub aliases: Vec<Alias>,
+    pub reserved: (W,W),
+    pub id: usize,
+}
+
+/*
+ * Another name for an instruction, like "mv" for "addi" with an
+ *  immediate of 0. It applies when every operand in <conds> has the
+ *  value given, and is printed with operands <ops>. Operands are
+ *  numbered from 0 here, and from 1 in scripts
+ */
+#[derive(Clone)]
+pub struct Alias {
+    pub name: String,
+    pub conds: Vec<(usize,i128)>,
+    pub ops: Vec<usize>,
+}
+
+/*
+ * A mode switch marked on an instruction. If <odd> it only happens when
+ *  the branch target is odd, and the low bit is not part of the target
+ *  (like ARM's bx and blx to Thumb code)
  */
-pub struct Instrfmt {
-    pub fmt: Vec<Fmt>,
+pub struct Switch {
+    pub mode: String,
+    pub odd: bool,
 }
 
-pub enum Node {
-    Map(Maskmap),
-    Instr((String,Instrfmt))
+/*
+ * A prefix word: decoding continues with the next word, looked up in
+ *  table <table>. <name> is printed before the instruction, if given
+ */
+pub struct Prefix {
+    pub name: Option<String>,
+    pub table: String,
+}
+
+pub enum Node<W: Word> {
+    Map(Maskmap<W>),
+    Instr((String,Instrfmt<W>)),
+    Prefix(Prefix),
 }
 
 // Bit-masking hashmap. Keys are words under the bitmask, vals are &Node
-pub struct Maskmap {
-    pub mask: Bitmask,
-    pub map: HashMap<Wordt, Node>
+pub struct Maskmap<W: Word> {
+    pub mask: W,
+    pub map: HashMap<W, Node<W>>
 }
 
-pub struct Instrset {
-    pub wordsize: usize,
-    pub endian_little: bool, // if false use big endian
-    pub set: Maskmap,
+/*
+ * Name of the root set, for prefixes which continue decoding in it.
+ * Also the name of the first mode, unless the script names it
+ */
+pub const ROOT_TABLE: &str = "main";
+
+/*
+ * One way of decoding a binary, such as ARM or Thumb
+ */
+pub struct Mode<W: Word> {
+    pub name: String,
+    pub layout: binreader::Layout,
+    pub set: Maskmap<W>,
 }
 
+pub struct Instrset<W: Word> {
+    // The first mode is the one used unless told otherwise
+    pub modes: Vec<Mode<W>>,
+    // Tables only reached through prefixes, by name
+    pub tables: HashMap<String, Maskmap<W>>,
+    // Ranges of every binary which are data, in the order declared
+    pub data: Vec<DataRange>,
+    // Aliases and the instructions they are for, in the order declared
+    pub aliases: Vec<(String,Alias)>,
+    // Problems which did not stop the script from loading: (line, what)
+    pub warnings: Vec<(u64,String)>,
+    // Words breaking reserved bits are unknown instructions, rather than
+    //  instructions flagged as bad. Set by the user, not the script
+    pub strict: bool,
+}
+
+impl<W: Word> Instrset<W> {
+    /*
+     * Table called <name>; ROOT_TABLE is the root set of <mode>
+     */
+    pub fn table(&self, mode: usize, name: &str) -> Option<&Maskmap<W>> {
+        match name {
+            ROOT_TABLE => Some(&self.modes[mode].set),
+            _other => self.tables.get(name),
+        }
+    }
+
+    /*
+     * Index of the mode called <name>
+     */
+    pub fn mode_index(&self, name: &str) -> Option<usize> {
+        self.modes.iter().position(|m| m.name==name)
+    }
+
+    /*
+     * Layout a binary is read with. Addresses count its words: those of
+     *  the only mode, or bytes if there are several modes
+     */
+    pub fn address_layout(&self) -> binreader::Layout {
+        match self.modes.len() {
+            1 => self.modes[0].layout.clone(),
+            _ => binreader::Layout::bytes(),
+        }
+    }
+
+    /*
+     * Bytes at each address, or 0 for packed words
+     */
+    pub fn address_bytes(&self) -> u64 {
+        self.address_layout().container as u64
+    }
+
+    /*
+     * How far apart the addresses of consecutive words of <mode> are
+     */
+    pub fn unit(&self, mode: usize) -> u64 {
+        match self.modes.len() {
+            1 => 1,
+            _ => self.modes[mode].layout.container as u64,
+        }
+    }
+
+    /*
+     * Names of the tables other than the root, in order
+     */
+    pub fn table_names(&self) -> Vec<&String> {
+        let mut names: Vec<&String>=self.tables.keys().collect();
+        names.sort();
+        names
+    }
+}
 
 
 /*
- * Given a Wordt and an instructions set,
- *  return the matching instruction name and Instrfmt
+ * Given a word and an instructions set,
+ *  return the matching instruction or prefix (never a Map)
  * Apply (Bitwise OR) all bitmasks searched to *<mask_total>
  */
-pub fn get_fmt<'a>(w: Wordt, mut set: &'a Maskmap, mask_total: &mut Bitmask) -> Option<&'a (String,Instrfmt)> {
+pub fn get_node<'a,W: Word>(w: W, mut set: &'a Maskmap<W>, mask_total: &mut W) -> Option<&'a Node<W>> {
     loop {
         *mask_total |= set.mask;
         match set.map.get(&(w&set.mask)) {
             None => {return None},
-            Some(n) => match n {
-                Node::Instr(tup) => {return Some(&tup)},
-                Node::Map(m)   => {set=&m;}
+            Some(Node::Map(m)) => {set=m;},
+            Some(n) => {return Some(n)},
+        }
+    }
+}
+
+/*
+ * Count the instructions and maps under <set> (including <set> itself),
+ *  and how deep the deepest map is
+ */
+pub fn count_nodes<W: Word>(set: &Maskmap<W>) -> (usize,usize,usize) {
+    let (mut instrs,mut maps,mut depth)=(0,1,1);
+    for n in set.map.values() { match n {
+        Node::Instr(_) | Node::Prefix(_) => {instrs+=1;},
+        Node::Map(m) => {
+            let (i,m,d)=count_nodes(m);
+            instrs+=i;
+            maps+=m;
+            depth=depth.max(d+1);
+        },
+    }}
+    (instrs,maps,depth)
+}
+
+/*
+ * Write <is> to <out> as a script: every mode with its header lines and
+ *  tree, then the other tables, and then the aliases. Opcodes are in
+ *  ascending order and every mask is spelled out in full.
+ */
+pub fn dump_tree<W: Word, O: Write>(is: &Instrset<W>, out: &mut O) -> io::Result<()> {
+    for (k,m) in is.modes.iter().enumerate() {
+        match k {
+            0 => {
+                writeln!(out,"{}",m.layout)?;
+                if m.name!=ROOT_TABLE {writeln!(out,"mode {}",m.name)?;}
+            },
+            _ => writeln!(out,"mode {} {}",m.name,m.layout)?,
+        }
+        if let Some(line)=m.layout.byte_order_line() {writeln!(out,"{}",line)?;}
+        if k==0 {
+            for r in &is.data {
+                writeln!(out,"data {:#x}:{:#x} {}",r.start,r.end,r.kind.keyword())?;
             }
         }
+        dump_map(&m.set,0,"",out)?;
+    }
+    for name in is.table_names() {
+        dump_map(&is.tables[name],0,&format!("table {} ",name),out)?;
+    }
+    for (instr,a) in &is.aliases {
+        write!(out,"alias {} = {}",a.name,instr)?;
+        if !a.conds.is_empty() {
+            write!(out," if")?;
+            for (k,v) in &a.conds {write!(out," {}={}",k+1,v)?;}
+        }
+        if !a.ops.is_empty() {
+            write!(out," ops")?;
+            for k in &a.ops {write!(out," {}",k+1)?;}
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/*
+ * Helper for dump_tree(); <head> goes before the map's mask
+ */
+fn dump_map<W: Word, O: Write>(set: &Maskmap<W>, depth: usize, head: &str, out: &mut O) -> io::Result<()> {
+    let indent="    ".repeat(depth);
+    let mut keys: Vec<&W>=set.map.keys().collect();
+    keys.sort();
+
+    writeln!(out,"{}{}mask {:#x} {{",indent,head,set.mask)?;
+    for k in keys {
+        let opcode=bits::minimize(*k,set.mask).0;
+        match &set.map[k] {
+            Node::Map(m) => dump_map(m,depth+1,&format!("{:#x} ",opcode),out)?,
+            Node::Instr((name,ifmt)) => {
+                write!(out,"{}    {:#x} = {}",indent,opcode,name)?;
+                match ifmt.ext {
+                    0 => (),
+                    1 => write!(out," +1 word")?,
+                    n => write!(out," +{} words",n)?,
+                }
+                for f in &ifmt.fmt {
+                    write!(out," {}",f.typ.keyword())?;
+                    match f.words {
+                        (0,0) => (),
+                        (a,b) if a==b => write!(out," word {}",a)?,
+                        (a,b) => write!(out," words {}:{}",a,b)?,
+                    }
+                    write!(out," mask {:#x}",f.mask)?;
+                    for op in &f.ops {
+                        write!(out," {} {:#x}",op.typ.symbol(),op.val)?;
+                    }
+                }
+                if ifmt.reserved.0!=W::ZERO {
+                    write!(out," reserved mask {:#x} = {:#x}",ifmt.reserved.0,bits::minimize(ifmt.reserved.1,ifmt.reserved.0).0)?;
+                }
+                if let Some(c)=&ifmt.class {
+                    write!(out," class {}",c.keyword())?;
+                }
+                if let Some(sw)=&ifmt.switch {
+                    write!(out," switch {}{}",sw.mode,match sw.odd {true=>" if odd", false=>""})?;
+                }
+                if let Some(d)=&ifmt.desc {
+                    write!(out," \"{}\"",d.replace('\\',"\\\\").replace('"',"\\\""))?;
+                }
+                writeln!(out)?;
+            },
+            Node::Prefix(p) => match &p.name {
+                Some(name) => writeln!(out,"{}    {:#x} = {} prefix {}",indent,opcode,name,p.table)?,
+                None => writeln!(out,"{}    {:#x} prefix {}",indent,opcode,p.table)?,
+            },
+        }
     }
+    writeln!(out,"{}}}",indent)
 }
@@ -0,0 +1,1144 @@
+/*
+ * ir.rs - decoded instructions
+ * A binary is decoded once into a list of Instr, which label resolution,
+ *  rendering and any other analyses then work from. The list can have an
+ *  Instr for every word of the binary, so each keeps no more than what
+ *  decoding found, and its operands are read from its word when asked for.
+ */
+use std::{
+    fmt::Display,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use super::instrset::{
+    self as instrset,
+    Instrset, Instrfmt,
+    Fmt, FmtType,
+    Node, Class, DataKind, Alias,
+    binreader::{self as binreader, Binreader, Layout},
+    bits as bits, bits::{
+        Word,
+        minimize,
+    },
+};
+
+/*
+ * One operand of a decoded instruction
+ */
+pub struct Operand<'a,W: Word> {
+    pub fmt: &'a Fmt<W>,
+    pub val: (W,W),          // data under fmt.mask after BitOps, and mask size
+    pub target: Option<u64>, // label this operand branches to, if any
+}
+
+pub struct Instr<'a,W: Word> {
+    pub addr: u64,
+    pub mode: usize, // index in Instrset.modes
+    pub len: u64, // in addresses, including prefixes and trailing words
+    pub word: W,  // word the instruction was matched from, after any prefixes
+    pub entry: Option<&'a (String,Instrfmt<W>)>, // name and format matched in the script
+    pub rest: Option<W>, // bits not under any mask, for the default formatter
+    pub data: Option<DataKind>, // if not code: how its words are shown, and <entry> is None
+    src: &'a dyn Fetch<W>, // where the words were read from, to read prefixes again
+    is: &'a Instrset<W>,
+}
+
+impl<'a,W: Word> Instr<'a,W> {
+    /*
+     * Name of the instruction, or the directive for data
+     */
+    pub fn name(&self) -> &'a str {
+        if let Some(entry)=self.entry {return &entry.0}
+        match self.data {
+            Some(DataKind::Byte) => ".byte",
+            Some(DataKind::Half) => ".half",
+            Some(DataKind::Word) => ".word",
+            Some(DataKind::Ascii) => ".ascii",
+            Some(DataKind::Asciz) => ".asciz",
+            Some(DataKind::Skip) => ".skip",
+            _other => match self.is.address_layout().wordbits {
+                8 => ".byte",
+                _ => ".word",
+            },
+        }
+    }
+
+    /*
+     * Description of the instruction from the script, if it has one
+     */
+    pub fn desc(&self) -> Option<&'a str> {
+        self.entry.and_then(|entry| entry.1.desc.as_deref())
+    }
+
+    /*
+     * The most specific alias whose conditions the operands meet, if any
+     */
+    pub fn alias(&self) -> Option<&'a Alias> {
+        let entry=self.entry?;
+        entry.1.aliases.iter().find(|a| a.conds.iter().all(|(k,v)| {
+            let d=self.val(&entry.1.fmt[*k]);
+            match *v<0 {
+                true  => bits::twoscomp(d).into()==*v,
+                false => d.0==W::from_u64(*v as u64),
+            }
+        }))
+    }
+
+    /*
+     * How the instruction changes the flow of control, if the script says
+     */
+    pub fn class(&self) -> Option<Class> {
+        self.entry.and_then(|entry| entry.1.class)
+    }
+
+    /*
+     * Reserved bits of the word which do not have their fixed values
+     */
+    pub fn reserved(&self) -> W {
+        match self.entry {
+            Some(entry) => (self.word ^ entry.1.reserved.1) & entry.1.reserved.0,
+            None => W::ZERO,
+        }
+    }
+
+    /*
+     * Word <k> of the instruction, counting from its first prefix.
+     * Decoding read it once already, so it is there to read again
+     */
+    fn word_at(&self, k: u64) -> W {
+        self.src.fetch(self.is,self.mode,self.addr+k*self.is.unit(self.mode)).ok()
+            .expect("Internal Error: word of a decoded instruction")
+    }
+
+    /*
+     * Every word of the instruction, in order. For data, every value:
+     *  the size of a range to skip, the words addresses count, or else
+     *  items of its kind read from the bytes it takes
+     */
+    pub fn words(&self) -> impl Iterator<Item=W> + '_ {
+        // count, and where each starts
+        let (n,first,step)=match self.data {
+            None => (self.len/self.is.unit(self.mode),0,0),
+            Some(DataKind::Skip) => (1,0,0),
+            Some(DataKind::Units) => (self.len,self.addr,1),
+            Some(kind) => {
+                let size=kind.size().unwrap_or(1);
+                let abytes=self.is.address_bytes();
+                (self.len*abytes/size,self.addr*abytes,size)
+            },
+        };
+        (0..n).map(move |k| match self.data {
+            None => self.word_at(k),
+            Some(DataKind::Skip) => self.word,
+            Some(kind) => self.src.data(self.is,first+k*step,kind),
+        })
+    }
+
+    /*
+     * Number of prefix words before the word the instruction was matched from
+     */
+    fn n_prefixes(&self) -> u64 {
+        match self.entry {
+            Some(entry) => self.len/self.is.unit(self.mode)-1-entry.1.ext as u64,
+            None => 0,
+        }
+    }
+
+    /*
+     * Prefix words of the instruction, and their names if printed
+     */
+    pub fn prefixes(&self) -> Vec<(W,Option<&'a str>)> {
+        let mut ret=Vec::new();
+        let mut set=&self.is.modes[self.mode].set;
+        let mut mask_total: W = W::ZERO; // just so get_node can track it
+        for k in 0..self.n_prefixes() {
+            let w=self.word_at(k);
+            let Some(Node::Prefix(p))=instrset::get_node(w,set,&mut mask_total) else {
+                unreachable!("Internal Error: prefix of a decoded instruction")
+            };
+            ret.push((w,p.name.as_deref()));
+            set=self.is.table(self.mode,&p.table).expect("Internal Error: prefix table checked by the parser");
+        }
+        ret
+    }
+
+    /*
+     * Data under the mask of <f>, one of the instruction's operands,
+     *  after BitOps
+     */
+    fn val(&self, f: &Fmt<W>) -> (W,W) {
+        let at=self.n_prefixes();
+        let mut d=match f.words {
+            (0,0) => minimize(self.word,f.mask),
+            span => minimize(join_words(|k| self.word_at(at+k),span,&self.is.modes[self.mode].layout),f.mask),
+        };
+        bits::apply_bit_ops(f.ops.iter(),&mut d.0);
+        d
+    }
+
+    /*
+     * Operands of the instruction, in the order of its format.
+     * If the instruction switches mode only to odd targets, the low bit
+     *  is not part of its first branch target
+     */
+    pub fn ops(&self) -> impl Iterator<Item=Operand<'a,W>> + '_ {
+        let unit=self.is.unit(self.mode);
+        let mut odd=self.entry.and_then(|entry| entry.1.switch.as_ref()).is_some_and(|sw| sw.odd);
+        self.entry.into_iter().flat_map(|entry| entry.1.fmt.iter()).map(move |f| {
+            let d=self.val(f);
+            let mut target=branch_target(&f.typ,d,self.addr,unit);
+            if let Some(t)=target.as_mut() {
+                if odd {*t&=!1;}
+                odd=false;
+            }
+            Operand {fmt: f, val: d, target}
+        })
+    }
+
+    /*
+     * Address and mode the instruction switches to, if any: the code
+     *  switched to starts at the first branch target, or after the
+     *  instruction if it does not branch
+     */
+    pub fn switch(&self) -> Option<(u64,usize)> {
+        let entry=self.entry?;
+        let sw=entry.1.switch.as_ref()?;
+        let to=self.is.mode_index(&sw.mode).expect("Internal Error: switch mode checked by the parser");
+        let unit=self.is.unit(self.mode);
+        match entry.1.fmt.iter().find_map(|f| branch_target(&f.typ,self.val(f),self.addr,unit)) {
+            Some(t) if sw.odd => match t&1 {
+                1 => Some((t&!1,to)),
+                _ => None,
+            },
+            Some(t) => Some((t,to)),
+            None => Some((self.addr+self.len,to)),
+        }
+    }
+}
+
+pub enum DecodeErrType<W: Word> {
+    UnknownOp,  // no instruction matches the word
+    Padding(W), // container bits above the word are not 0
+    Truncated,  // instruction runs past the end of the input
+    Overlap(u64), // instruction at the given address runs into another one
+    IntoData,     // instruction runs into a range of data
+    Reserved(W),  // reserved bits given do not have their fixed values
+}
+pub struct DecodeErr<W: Word> {
+    pub addr: u64,
+    pub word: W,
+    pub typ: DecodeErrType<W>,
+}
+impl<W: Word> Display for DecodeErr<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self.typ {
+            DecodeErrType::UnknownOp =>
+                write!(f,"[At {:#x}] Unknown instruction: {:#x}",self.addr,self.word),
+            DecodeErrType::Padding(p) =>
+                write!(f,"[At {:#x}] Padding bits {:#x} are set around word {:#x}",self.addr,p,self.word),
+            DecodeErrType::Truncated =>
+                write!(f,"[At {:#x}] Instruction runs past the end of the input",self.addr),
+            DecodeErrType::Reserved(r) =>
+                write!(f,"[At {:#x}] Unknown instruction: {:#x} (reserved bits {:#x} are wrong)",self.addr,self.word,r),
+            DecodeErrType::IntoData =>
+                write!(f,"[At {:#x}] Instruction {:#x} runs into data",self.addr,self.word),
+            DecodeErrType::Overlap(start) =>
+                write!(f,"[At {:#x}] Instruction {:#x} from {:#x} runs into the code here",self.addr,self.word,start),
+        }
+    }
+}
+
+/*
+ * Errors decoding, each with the mode it was decoded in
+ */
+pub type ModeErrs<W> = Vec<(usize,DecodeErr<W>)>;
+
+/*
+ * Label that a branch operand of type <typ> with data <d> (as returned
+ *  by bits::minimize, after BitOps) points to, for an instruction at <i>.
+ * Relative branches count in words, which are <unit> addresses apart;
+ *  sbranch operands are addresses already.
+ * None if <typ> is not a branch
+ */
+pub fn branch_target<W: Word>(typ: &FmtType, d: (W,W), i: u64, unit: u64) -> Option<u64> {
+    match typ {
+        FmtType::Ubranch => Some(i.wrapping_sub(d.0.to_u64().wrapping_mul(unit))),
+        FmtType::Dbranch => Some(i.wrapping_add(d.0.to_u64().wrapping_mul(unit))),
+        FmtType::Ibranch => Some((i as i128).wrapping_add(bits::twoscomp(d).into().wrapping_mul(unit as i128)) as u64),
+        FmtType::Sbranch => Some(d.0.to_u64()),
+        _other => None,
+    }
+}
+
+/*
+ * Mode of the code from each address on, until the next address in the
+ *  map. Code before the first address is in the first mode
+ */
+pub type ModeMap = BTreeMap<u64,usize>;
+
+/*
+ * Decoding passes at most, while mode switches keep changing a ModeMap
+ */
+pub const MAX_PASSES: usize = 8;
+
+/*
+ * Mode of the code at <addr>
+ */
+pub fn mode_at(modes: &ModeMap, addr: u64) -> usize {
+    match modes.range(..=addr).next_back() {
+        Some((_,m)) => *m,
+        None => 0,
+    }
+}
+
+/*
+ * Put addresses <start> up to (but not including) <end> in <mode>.
+ * Without an <end>, until the next address already in <modes>
+ */
+pub fn set_mode(modes: &mut ModeMap, start: u64, end: Option<u64>, mode: usize) {
+    if let Some(end)=end {
+        if end<=start {return}
+        let after=mode_at(modes,end);
+        let inside: Vec<u64>=modes.range(start..end).map(|(a,_)| *a).collect();
+        for a in inside {modes.remove(&a);}
+        modes.insert(end,after);
+    }
+    modes.insert(start,mode);
+}
+
+/*
+ * Ranges of data, by start address: the end of each range and its kind.
+ * Ranges do not overlap
+ */
+pub type DataMap = BTreeMap<u64,(u64,DataKind)>;
+
+/*
+ * Make addresses <start> up to <end> data of <kind>, replacing whatever
+ *  ranges of <data> were there
+ */
+pub fn set_data(data: &mut DataMap, start: u64, end: u64, kind: DataKind) {
+    if end<=start {return}
+    // a range from before <start> keeps its part before <start>, and
+    //  its part after <end> if it runs past it
+    if let Some((&a,&(e,k)))=data.range(..start).next_back() {
+        if e>start {
+            data.insert(a,(start,k));
+            if e>end {data.insert(end,(e,k));}
+        }
+    }
+    let inside: Vec<(u64,(u64,DataKind))>=data.range(start..end).map(|(a,v)| (*a,*v)).collect();
+    for (a,(e,k)) in inside {
+        data.remove(&a);
+        if e>end {data.insert(end,(e,k));}
+    }
+    data.insert(start,(end,kind));
+}
+
+/*
+ * End of the range of data which address <at> is in, if any
+ */
+pub fn data_end(data: &DataMap, at: u64) -> Option<u64> {
+    match data.range(..=at).next_back() {
+        Some((_,(e,_))) if *e>at => Some(*e),
+        _other => None,
+    }
+}
+
+/*
+ * Error if <ins> runs into a range of <data>
+ */
+fn check_data<'a,W: Word>(data: &DataMap, ins: Instr<'a,W>) -> Result<Instr<'a,W>,DecodeErr<W>> {
+    match data.range(ins.addr+1..ins.addr+ins.len).next() {
+        Some(_) => Err(DecodeErr {addr: ins.addr, word: ins.word, typ: DecodeErrType::IntoData}),
+        None => Ok(ins),
+    }
+}
+
+/*
+ * Words <first>..=<last> of an instruction joined into one, in the
+ *  byte order of <layout>. <word> gives word k of the instruction, where
+ *  word 0 is the opcode word, followed by its trailing words
+ */
+fn join_words<W: Word>(word: impl Fn(u64) -> W, (first,last): (usize,usize), layout: &Layout) -> W {
+    let mut ret=W::ZERO;
+    for k in first..=last {
+        let x=word(k as u64);
+        if layout.endian_little {ret |= x<<(layout.wordbits*(k-first)) as u32;}
+        else {
+            if k>first {ret<<=layout.wordbits as u32;}
+            ret |= x;
+        }
+    }
+    ret
+}
+
+/*
+ * Decode the instruction starting at address <i> in mode <mode>,
+ *  getting its words from <src>. Prefix words are followed into their
+ *  tables until an instruction is found.
+ */
+pub fn decode<'a,W: Word>(src: &'a dyn Fetch<W>, i: u64, mode: usize, is: &'a Instrset<W>) -> Result<Instr<'a,W>,DecodeErr<W>> {
+    let layout=&is.modes[mode].layout;
+    let unit=is.unit(mode);
+    let mut set=&is.modes[mode].set;
+    let mut at=i;
+
+    loop {
+        let w=src.fetch(is,mode,at)?;
+        let mut mask_total: W = W::ZERO;
+
+        match instrset::get_node(w,set,&mut mask_total) {
+            None => {return Err(DecodeErr {addr: i, word: w, typ: DecodeErrType::UnknownOp})},
+            Some(Node::Prefix(p)) => {
+                at+=unit;
+                set=is.table(mode,&p.table).expect("Internal Error: prefix table checked by the parser");
+            },
+            Some(Node::Map(_)) => unreachable!(),
+            Some(Node::Instr(entry)) => {
+                // reserved bits must have their values, and are not left
+                //  for the default formatter
+                let reserved=(w ^ entry.1.reserved.1) & entry.1.reserved.0;
+                if reserved!=W::ZERO && is.strict {
+                    return Err(DecodeErr {addr: i, word: w, typ: DecodeErrType::Reserved(reserved)})
+                }
+                mask_total |= entry.1.reserved.0;
+
+                // trailing words are only read here to know they are there
+                for k in 1..=entry.1.ext as u64 {src.fetch(is,mode,at+k*unit)?;}
+                for f in entry.1.fmt.iter().filter(|f| f.words==(0,0)) {mask_total |= f.mask;}
+
+                return Ok(Instr {
+                    addr: i,
+                    mode,
+                    len: at-i+(1+entry.1.ext as u64)*unit,
+                    word: w,
+                    entry: Some(entry),
+                    // Instruction parts without a format are left for the
+                    //  default formatter if mask_total is less than the
+                    //  maximum possible word of <layout.wordbits> bits,
+                    //  i.e. 0b11111111 for 8 bit words
+                    rest: match mask_total < bits::low_bits(layout.wordbits) {
+                        true  => Some(minimize(w,!mask_total).0),
+                        false => None,
+                    },
+                    data: None,
+                    src,
+                    is,
+                })
+            },
+        }
+    }
+}
+
+/*
+ * Where decode() reads the words of instructions from, by address
+ */
+pub trait Fetch<W: Word>: Sync {
+    /*
+     * Word of <mode> of <is> at address <at>
+     */
+    fn fetch(&self, is: &Instrset<W>, mode: usize, at: u64) -> Result<W,DecodeErr<W>>;
+
+    /*
+     * Item of data of <kind> at <at>: for Units the word at address <at>,
+     *  and for the rest the item starting at byte <at>, its bytes joined
+     *  in the byte order of the first mode (text is one byte at a time)
+     */
+    fn data(&self, is: &Instrset<W>, at: u64, kind: DataKind) -> W;
+}
+
+/*
+ * Words of the file wrapped by a Binreader
+ */
+impl<W: Word> Fetch<W> for Binreader<W> {
+    fn fetch(&self, is: &Instrset<W>, mode: usize, at: u64) -> Result<W,DecodeErr<W>> {
+        let (w,pad)=match is.modes.len() {
+            1 if at<self.n_instrs => Some((self.word(at),self.padding(at))),
+            1 => None,
+            _ => self.word_at(&is.modes[mode].layout,at),
+        }.ok_or(DecodeErr {addr: at, word: W::ZERO, typ: DecodeErrType::Truncated})?;
+        if pad!=W::ZERO {
+            return Err(DecodeErr {addr: at, word: w, typ: DecodeErrType::Padding(pad)})
+        }
+        Ok(w)
+    }
+
+    fn data(&self, is: &Instrset<W>, at: u64, kind: DataKind) -> W {
+        if kind==DataKind::Units {return self.word(at)}
+        let n=kind.size().unwrap_or(1);
+        let b=self.bytes(at as usize..(at+n) as usize);
+        match is.modes[0].layout.endian_little {
+            true  => W::from_le_slice(b),
+            false => W::from_be_slice(b),
+        }
+    }
+}
+
+/*
+ * Words given one by one, as to the decode command. Word <first> is at
+ *  address <addr>, and the words after it are at the following
+ *  addresses of the mode they are fetched in
+ */
+pub struct Given<'w,W: Word> {
+    pub words: &'w [W],
+    pub first: usize,
+    pub addr: u64,
+}
+
+impl<W: Word> Fetch<W> for Given<'_,W> {
+    fn fetch(&self, is: &Instrset<W>, mode: usize, at: u64) -> Result<W,DecodeErr<W>> {
+        match self.words.get(self.first+((at-self.addr)/is.unit(mode)) as usize) {
+            Some(w) => Ok(*w),
+            None => Err(DecodeErr {addr: at, word: W::ZERO, typ: DecodeErrType::Truncated}),
+        }
+    }
+
+    fn data(&self, _is: &Instrset<W>, at: u64, kind: DataKind) -> W {
+        match kind {
+            DataKind::Units => self.words[self.first+(at-self.addr) as usize],
+            _other => unreachable!("Internal Error: only binary files have ranges of data"),
+        }
+    }
+}
+
+/*
+ * Decode the instruction at <i> of the file wrapped by <br>, in the mode
+ *  <modes> gives for <i>. It must not run into the ranges of <data>
+ */
+fn decode_at<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &ModeMap, data: &DataMap, i: u64) -> Result<Instr<'a,W>,DecodeErr<W>> {
+    check_data(data,decode(br,i,mode_at(modes,i),is)?)
+}
+
+/*
+ * Instructions decoded from part of a file, and the error that stopped
+ *  decoding with the address its instruction started at
+ */
+pub type Decoded<'a,W> = (Vec<Instr<'a,W>>, Option<(u64,DecodeErr<W>)>);
+
+/*
+ * Decode the instructions starting in addresses <range> of the file
+ *  wrapped by <br>, skipping the ranges of <data>. The last one may run
+ *  past the end of <range>.
+ * Stops at the first error.
+ */
+pub fn decode_range<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &ModeMap, data: &DataMap, range: std::ops::Range<u64>) -> Decoded<'a,W> {
+    let mut out=Vec::with_capacity((range.end-range.start) as usize);
+    let mut i=range.start;
+    while i<range.end {
+        if let Some(e)=data_end(data,i) {i=e; continue}
+        match decode_at(br,is,modes,data,i) {
+            Ok(ins) => {i+=ins.len; out.push(ins);},
+            Err(why) => {return (out,Some((i,why)))},
+        }
+    }
+    (out,None)
+}
+
+/*
+ * Decode the file wrapped by <br> from address <start> to the end but
+ *  for the ranges of <data>, with modes from <modes>, using up to <jobs> threads. The instructions are
+ *  appended to <ret>.
+ * On failure decoding stops at the error closest to <start>, which is
+ *  returned.
+ */
+fn decode_pass<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &ModeMap, data: &DataMap, start: u64, jobs: usize, ret: &mut Vec<Instr<'a,W>>)
+-> Option<DecodeErr<W>> {
+    let mut err: Option<DecodeErr<W>>=None;
+    let mut next: u64=start; // where the next instruction starts
+    let chunks: Vec<std::ops::Range<u64>>=br.chunks().into_iter()
+        .filter(|range| range.end>start)
+        .map(|range| range.start.max(start)..range.end)
+        .collect();
+
+    binreader::par_map(&chunks, jobs,
+        |range| {
+            let (v,fail)=decode_range(br,is,modes,data,range.clone());
+            (range.end,v,fail)
+        },
+        |(end,mut v,fail)| {
+            // The last instruction of the previous chunk may run into this
+            //  one, so this chunk can have been decoded from the wrong
+            //  word. Decode again from <next> until reaching a word the
+            //  chunk was decoded from; everything after that is the same.
+            let mut k=v.partition_point(|ins| ins.addr<next);
+            while next<end
+                && v.get(k).is_none_or(|ins| ins.addr!=next)
+                && fail.as_ref().is_none_or(|(at,_)| *at!=next) {
+                match data_end(data,next) {
+                    Some(e) => {next=e;},
+                    None => match decode_at(br,is,modes,data,next) {
+                        Ok(ins) => {next+=ins.len; ret.push(ins);},
+                        Err(why) => {err=Some(why); return false},
+                    },
+                }
+                while v.get(k).is_some_and(|ins| ins.addr<next) {k+=1;}
+            }
+            if next>=end {return true}
+
+            ret.extend(v.drain(k..));
+            if let Some(last)=ret.last() {next=next.max(last.addr+last.len);}
+            match fail {
+                Some((_,why)) => {err=Some(why); false},
+                None => true,
+            }
+        });
+
+    err
+}
+
+/*
+ * Decode the whole file wrapped by <br> but for the ranges of <data>,
+ *  using up to <jobs> threads.
+ * Code is decoded in the modes <modes> gives, and mode switches found
+ *  in it are added to <modes>. Where that changes the mode of code
+ *  already decoded, decoding goes on again from the first instruction
+ *  it changes, until the switches stop changing <modes> (or after
+ *  MAX_PASSES). Switches found before an error count too, since the
+ *  error may be code decoded in the wrong mode.
+ * On failure the error closest to the start of the file is returned.
+ */
+pub fn decode_file<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &mut ModeMap, data: &DataMap, jobs: usize) -> Result<Vec<Instr<'a,W>>,DecodeErr<W>> {
+    let mut ret: Vec<Instr<'a,W>>=Vec::new();
+    let mut start: u64=0;
+    let mut pass=1;
+    loop {
+        let err=decode_pass(br,is,modes,data,start,jobs,&mut ret);
+
+        // first address whose mode changed
+        let mut changed: Option<u64>=None;
+        if is.modes.len()>1 && pass<MAX_PASSES {
+            for (to,m) in ret.iter().filter_map(|ins| ins.switch()) {
+                if to<br.n_instrs && mode_at(modes,to)!=m {
+                    modes.insert(to,m);
+                    changed=Some(changed.map_or(to,|c| c.min(to)));
+                }
+            }
+        }
+        match (changed,err) {
+            (Some(at),_) => {
+                // instructions before <at> decode the same as before
+                ret.truncate(ret.partition_point(|ins| ins.addr<at));
+                start=ret.last().map_or(0,|last| last.addr+last.len);
+                pass+=1;
+            },
+            (None,Some(why)) => {return Err(why)},
+            (None,None) => {return Ok(ret)},
+        }
+    }
+}
+/*
+ * Decode the whole file wrapped by <br> but for the ranges of <data>, in
+ *  one thread, carrying on past failures: decoding starts again one
+ *  unit after the start of each instruction which fails.
+ * Code is decoded in the modes <modes> gives, and mode switches take
+ *  effect from where they switch to, if that comes later.
+ * Returns the instructions, and the errors with the mode they were
+ *  decoded in, both in address order
+ */
+pub fn decode_sweep<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &ModeMap, data: &DataMap)
+-> (Vec<Instr<'a,W>>, ModeErrs<W>) {
+    let mut modes=modes.clone();
+    let mut out=Vec::new();
+    let mut errs=Vec::new();
+    let mut i=0;
+    while i<br.n_instrs {
+        if let Some(e)=data_end(data,i) {i=e; continue}
+        let mode=mode_at(&modes,i);
+        match decode_at(br,is,&modes,data,i) {
+            Ok(ins) => {
+                if let Some((to,m))=ins.switch() {
+                    if to>i && to<br.n_instrs {modes.insert(to,m);}
+                }
+                i+=ins.len;
+                out.push(ins);
+            },
+            Err(why) => {
+                errs.push((mode,why));
+                i+=is.unit(mode);
+            },
+        }
+    }
+    (out,errs)
+}
+
+/*
+ * Whether the instruction after <ins> can run after it
+ */
+fn falls_through<W: Word>(ins: &Instr<W>) -> bool {
+    !matches!(ins.class(), Some(Class::Jump) | Some(Class::Return) | Some(Class::Halt))
+}
+
+/*
+ * Decode only the code reached from <entries> in the file wrapped by
+ *  <br>: every branch target is followed, and decoding carries on after
+ *  each instruction but unconditional jumps, returns and halts. Paths
+ *  stop at the ranges of <data>.
+ * Entries start in the modes <modes> gives for them; branch targets are
+ *  in the mode of the branch, unless it switches mode.
+ * Returns the instructions in address order, and the errors which ended
+ *  a path with the mode they were decoded in, also in address order. Code
+ *  running into an instruction already decoded from another address is
+ *  an error too.
+ */
+pub fn decode_reached<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, modes: &ModeMap, data: &DataMap, entries: &[u64])
+-> (Vec<Instr<'a,W>>, ModeErrs<W>) {
+    let mut found: BTreeMap<u64,Instr<'a,W>>=BTreeMap::new();
+    let mut errs: BTreeMap<u64,(usize,DecodeErr<W>)>=BTreeMap::new();
+    let mut todo: Vec<(u64,usize)>=entries.iter().rev().map(|a| (*a,mode_at(modes,*a))).collect();
+
+    while let Some((mut i,mut mode))=todo.pop() {
+        while i<br.n_instrs && data_end(data,i).is_none() {
+            // already decoded, or inside something which was
+            if found.range(..=i).next_back().is_some_and(|(a,ins)| a+ins.len>i) {break}
+
+            let ins=match decode(br,i,mode,is).and_then(|ins| check_data(data,ins)) {
+                Ok(ins) => ins,
+                Err(why) => {errs.entry(i).or_insert((mode,why)); break},
+            };
+            if let Some((a,_))=found.range(i+1..i+ins.len).next() {
+                errs.entry(i).or_insert((mode,DecodeErr {addr: *a, word: ins.word, typ: DecodeErrType::Overlap(i)}));
+                break
+            }
+
+            // later paths are taken first, so push in reverse
+            let switch=ins.switch();
+            let targets: Vec<u64>=ins.ops().filter_map(|op| op.target).collect();
+            for t in targets.into_iter().rev() {
+                match switch {
+                    Some((to,m)) if to==t => todo.push((t,m)),
+                    _other => todo.push((t,mode)),
+                }
+            }
+            let next=i+ins.len;
+            if let Some((to,m))=switch {
+                if to==next {mode=m;}
+            }
+            let go_on=falls_through(&ins);
+            found.insert(i,ins);
+            if !go_on {break}
+            i=next;
+        }
+    }
+    (found.into_values().collect(), errs.into_values().collect())
+}
+
+/*
+ * Length in bytes of the printable character at the start of <b>, if
+ *  there is one: printable ASCII, a tab or line break, or a character
+ *  encoded in UTF-8 which is not a control character
+ */
+fn printable(b: &[u8]) -> Option<usize> {
+    match b.first()? {
+        b' '..=b'~' | b'\t' | b'\n' | b'\r' => Some(1),
+        0xc2..=0xf4 => {
+            let n=match b[0] {0xc2..=0xdf => 2, 0xe0..=0xef => 3, _ => 4};
+            let c=std::str::from_utf8(b.get(..n)?).ok()?.chars().next()?;
+            (!c.is_control()).then_some(n)
+        },
+        _other => None,
+    }
+}
+
+/*
+ * Runs of at least <min> printable characters in <bytes>: where each
+ *  starts and ends, and whether it is followed by a NUL. The end takes in
+ *  the NUL if there is one
+ */
+fn find_strings(bytes: &[u8], min: usize) -> Vec<(usize,usize,bool)> {
+    let mut ret=Vec::new();
+    let mut i=0;
+    while i<bytes.len() {
+        let (start,mut chars)=(i,0);
+        while let Some(n)=printable(&bytes[i..]) {
+            i+=n;
+            chars+=1;
+        }
+        if chars==0 {i+=1; continue}
+        let nul=bytes.get(i)==Some(&0);
+        if nul {i+=1;}
+        if chars>=min {ret.push((start,i,nul));}
+    }
+    ret
+}
+
+/*
+ * Numbers which can go on one line of data
+ */
+pub const DATA_PER_LINE: u64 = 8;
+
+/*
+ * Characters which can go on one line of text
+ */
+pub const TEXT_PER_LINE: u64 = 64;
+
+/*
+ * Fill the gaps between <code> (in address order) with data for every
+ *  word of the file wrapped by <br>: the ranges of <data> as their kind
+ *  says, and the rest as Units. Each item holds a line's worth of data,
+ *  and items also start at every branch target, so they can be labelled.
+ * Ranges of data which are not a whole number of items end in Units.
+ * Runs of at least <min_string> printable characters in Units or bytes
+ *  become text (if <min_string> is not 0), as long as they fill whole
+ *  addresses
+ */
+pub fn fill_data<'a,W: Word>(br: &'a Binreader<W>, is: &'a Instrset<W>, data: &DataMap, min_string: usize, code: Vec<Instr<'a,W>>) -> Vec<Instr<'a,W>> {
+    let targets: BTreeSet<u64>=code.iter()
+        .flat_map(|ins| ins.ops().filter_map(|op| op.target))
+        .collect();
+    let abytes=is.address_bytes();
+
+    // items of <kind> for addresses <start> up to <end>
+    let items=|ret: &mut Vec<Instr<'a,W>>, start: u64, end: u64, kind: DataKind| {
+        // addresses in each value, and addresses in each line
+        let step=match kind.size() {
+            Some(n) if n>=abytes && abytes>0 => n/abytes,
+            _other => 1,
+        };
+        let kind=match kind.size() {
+            Some(n) if n!=step*abytes => DataKind::Units,
+            _other => kind,
+        };
+        let per_line=match kind {
+            DataKind::Ascii | DataKind::Asciz => (TEXT_PER_LINE/abytes.max(1)).max(1),
+            DataKind::Skip => u64::MAX,
+            _other => DATA_PER_LINE*step,
+        };
+
+        let mut a=start;
+        let whole=start+(end-start)/step*step;
+        while a<whole {
+            let stop=targets.range(a+1..whole).find(|t| (**t-start).is_multiple_of(step)).copied()
+                .unwrap_or(whole)
+                .min(a.saturating_add(per_line));
+            // only the last line of a NUL terminated string ends in NUL
+            let kind=match kind {
+                DataKind::Asciz if stop<whole => DataKind::Ascii,
+                k => k,
+            };
+            ret.push(Instr {
+                addr: a,
+                mode: 0,
+                len: stop-a,
+                word: match kind {
+                    DataKind::Skip => W::from_u64((stop-a)*abytes),
+                    DataKind::Units => br.word(a),
+                    _other => Fetch::data(br,is,a*abytes,kind),
+                },
+                entry: None,
+                rest: None,
+                data: Some(kind),
+                src: br,
+                is,
+            });
+            a=stop;
+        }
+        a
+    };
+    // items of <kind> for addresses <start> up to <end>, and Units for
+    //  any left over
+    let whole=|ret: &mut Vec<Instr<'a,W>>, start: u64, end: u64, kind: DataKind| {
+        let done=items(ret,start,end,kind);
+        items(ret,done,end,DataKind::Units);
+    };
+    // everything from <start> up to <end>, with any strings in numbers
+    //  of bytes or Units as text
+    let fill=|ret: &mut Vec<Instr<'a,W>>, start: u64, end: u64| {
+        let mut a=start;
+        while a<end {
+            let (stop,kind)=match data.range(..=a).next_back() {
+                Some((_,(e,k))) if *e>a => ((*e).min(end),*k),
+                _other => (data.range(a..end).next().map_or(end,|(s,_)| *s),DataKind::Units),
+            };
+            let mut at=a;
+            if matches!(kind,DataKind::Units | DataKind::Byte) && min_string>0 && abytes>0 {
+                let base=a*abytes;
+                let bytes=br.bytes(base as usize..(stop*abytes) as usize);
+                for (s,e,nul) in find_strings(bytes,min_string) {
+                    // strings only take whole addresses
+                    let (s,e)=(base+s as u64,base+e as u64);
+                    let (sa,ea)=(s.div_ceil(abytes),e/abytes);
+                    if ea<=sa || (ea-sa)*abytes<min_string as u64 {continue}
+                    whole(ret,at,sa,kind);
+                    whole(ret,sa,ea,match nul && e.is_multiple_of(abytes) {
+                        true  => DataKind::Asciz,
+                        false => DataKind::Ascii,
+                    });
+                    at=ea;
+                }
+            }
+            whole(ret,at,stop,kind);
+            a=stop;
+        }
+    };
+
+    let mut ret=Vec::with_capacity(code.len());
+    let mut next=0;
+    for ins in code {
+        fill(&mut ret,next,ins.addr);
+        next=ins.addr+ins.len;
+        ret.push(ins);
+    }
+    fill(&mut ret,next,br.n_instrs);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use binreader::CHUNK_WORDS;
+
+    fn script(text: &str) -> Instrset<u64> {
+        match parse::parse_file(text.as_bytes()) {
+            Ok(is) => is,
+            Err((why,ln)) => panic!("line {}: {}",ln,why),
+        }
+    }
+
+    fn reader(is: &Instrset<u64>, bytes: &[u8]) -> Binreader<u64> {
+        match Binreader::from_reader(bytes,&is.address_layout()) {
+            Ok(br) => br,
+            Err(why) => panic!("{}",why),
+        }
+    }
+
+    // bytes with the low bit set start an instruction of three
+    const LONG: &str="1 byte words\nmask 0x03 {\n0x0 = nop\n0x1 prefix a\n}\n\
+        table a mask 0x03 {\n0x0 prefix b\n0x1 prefix b\n0x2 prefix b\n0x3 prefix b\n}\n\
+        table b mask 0x03 {\n0x0 = long\n0x1 = long\n0x2 = long\n0x3 = long\n}\n";
+
+    // address and length of each instruction
+    type Spans=Vec<(u64,u64)>;
+
+    /*
+     * Instructions decode_file() finds with <jobs> threads, and the ones
+     *  one decode_range() over the whole file finds
+     */
+    fn both_ways(is: &Instrset<u64>, br: &Binreader<u64>, jobs: usize) -> (Spans,Spans) {
+        let got=match decode_file(br,is,&mut ModeMap::new(),&DataMap::new(),jobs) {
+            Ok(v) => v,
+            Err(why) => panic!("{}",why),
+        };
+        let (want,fail)=decode_range(br,is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        if let Some((_,why))=fail {panic!("{}",why)}
+        (got.iter().map(|ins| (ins.addr,ins.len)).collect(),want.iter().map(|ins| (ins.addr,ins.len)).collect())
+    }
+
+    #[test]
+    fn test_prefix() {
+        let is=script("1 byte words\nmask 0x03 {\n0x0 = nop\n0x1 prefix p\n0x2 = rep prefix p\n}\n\
+            table p mask 0x03 {\n0x0 = add\n0x1 = sub uint 2:7\n}\n");
+        let br=reader(&is,&[0x01,0x05,0x02,0x00,0x00]);
+        let (code,fail)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        assert!(fail.is_none(),"Failed");
+        let spans: Spans=code.iter().map(|ins| (ins.addr,ins.len)).collect();
+        assert!(spans==[(0,2),(2,2),(4,1)],"Actual: {:?}",spans);
+        let names: Vec<&str>=code.iter().map(|ins| ins.name()).collect();
+        assert!(names==["sub","add","nop"],"Actual: {:?}",names);
+
+        let words: Vec<u64>=code[0].words().collect();
+        let ops: Vec<u64>=code[0].ops().map(|op| op.val.0).collect();
+        assert!(words==[0x01,0x05] && ops==[1],"Actual: {:?} {:?}",words,ops);
+        let prefixes=code[1].prefixes();
+        assert!(prefixes==[(0x02,Some("rep"))],"Actual: {:?}",prefixes);
+    }
+
+    #[test]
+    fn test_trailing_words() {
+        // the same bytes joined in each byte order
+        for (order,want) in [("","0x1234"),(" nonnative endian","0x3412")] {
+            let is=script(&format!("1 byte{} words\nmask 0xff {{\n\
+                0x0 = ld +2 words uint words 1:2 0:15\n0x1 = nop\n}}\n",order));
+            let br=reader(&is,&[0x00,0x34,0x12,0x01]);
+            let (code,fail)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+            assert!(fail.is_none(),"Failed");
+            let spans: Spans=code.iter().map(|ins| (ins.addr,ins.len)).collect();
+            assert!(spans==[(0,3),(3,1)],"Actual: {:?}",spans);
+            let ops: Vec<String>=code[0].ops().map(|op| format!("{:#x}",op.val.0)).collect();
+            assert!(ops==[want],"{}: {:?}",order,ops);
+        }
+
+        // trailing words past the end of the file
+        let is=script("1 byte words\nmask 0xff {\n0x0 = ld +2 words uint word 2 0:7\n}\n");
+        let br=reader(&is,&[0x00,0x34]);
+        let (code,fail)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        assert!(code.is_empty() && matches!(fail,Some((0,DecodeErr {typ: DecodeErrType::Truncated, ..}))),"Not truncated");
+    }
+
+    #[test]
+    fn test_reached() {
+        let is=script("1 byte words\nmask 0xf0 {\n0x1 = jmp sbranch 0:3 class jump\n0x2 = beq sbranch 0:3\n\
+            0x3 = ld +1 word\n0x4 = ret class return\n}\n");
+        // the ld at 6 runs into the ret at 7, reached first
+        let br=reader(&is,&[0x26,0x17,0xff,0xff,0xff,0xff,0x30,0x40,0xff]);
+        let (code,errs)=decode_reached(&br,&is,&ModeMap::new(),&DataMap::new(),&[0]);
+        let spans: Spans=code.iter().map(|ins| (ins.addr,ins.len)).collect();
+        assert!(spans==[(0,1),(1,1),(7,1)],"Actual: {:?}",spans);
+        assert!(errs.len()==1 && matches!(errs[0],(0,DecodeErr {addr: 7, typ: DecodeErrType::Overlap(6), ..})),"Not an overlap");
+
+        // nothing after the jump or the return was decoded
+        let all=fill_data(&br,&is,&DataMap::new(),0,code);
+        let spans: Vec<(u64,u64,bool)>=all.iter().map(|ins| (ins.addr,ins.len,ins.data.is_some())).collect();
+        assert!(spans==[(0,1,false),(1,1,false),(2,4,true),(6,1,true),(7,1,false),(8,1,true)],"Actual: {:?}",spans);
+        let words: Vec<u64>=all[2].words().collect();
+        assert!(all[2].name()==".byte" && words==[0xff; 4],"Actual: {} {:?}",all[2].name(),words);
+    }
+
+    #[test]
+    fn test_alias() {
+        let is=script("4 byte words\nmask 0x707f {\n\
+            0x13 = addi uint mask 0xf80 uint mask 0xf8000 int mask 0xfff00000\n}\n\
+            alias nop = addi if 1=0 2=0 3=0\n\
+            alias mv = addi if 3=0 ops 1 2\n\
+            alias dec = addi if 3=-1 ops 1\n");
+        let words: [u32; 4]=[0x00000013,0x00058513,0xfff50513,0x00558513];
+        let bytes: Vec<u8>=words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let br=reader(&is,&bytes);
+        let (code,_)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        let names: Vec<Option<&str>>=code.iter().map(|ins| ins.alias().map(|a| a.name.as_str())).collect();
+        assert!(names==[Some("nop"),Some("mv"),Some("dec"),None],"Actual: {:?}",names);
+    }
+
+    #[test]
+    fn test_reserved() {
+        let mut is=script("1 byte words\nmask 0xf0 {\n0x1 = inc uint mask 0x3 reserved bits 2:3 = 0\n}\n");
+        let br=reader(&is,b"\x11\x15");
+        let (code,fail)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        let got: Vec<(u64,Option<u64>)>=code.iter().map(|ins| (ins.reserved(),ins.rest)).collect();
+        assert!(fail.is_none() && got==[(0,None),(0x4,None)],"Actual: {:?}",got);
+
+        // in strict mode, the word breaking them is not an instruction
+        is.strict=true;
+        let (code,fail)=decode_range(&br,&is,&ModeMap::new(),&DataMap::new(),0..br.n_instrs);
+        assert!(code.len()==1 && matches!(fail,Some((1,DecodeErr {typ: DecodeErrType::Reserved(0x4), ..}))),"Not reserved");
+    }
+
+    #[test]
+    fn test_sweep() {
+        // every unknown word fails on its own, and decoding goes on after it
+        let is=script("1 byte words\nmask 0xf0 {\n0x0 = nop\n0x1 = ld +1 word uint word 1 0:7\n}\n");
+        let br=reader(&is,b"\x00\xf0\xe0\x10\xf0\x00\xf1");
+        let (code,errs)=decode_sweep(&br,&is,&ModeMap::new(),&DataMap::new());
+        let spans: Spans=code.iter().map(|ins| (ins.addr,ins.len)).collect();
+        let failed: Vec<u64>=errs.iter().map(|(_,why)| why.addr).collect();
+        assert!(spans==[(0,1),(3,2),(5,1)] && failed==[1,2,6],"Actual: {:?} {:?}",spans,failed);
+    }
+
+    #[test]
+    fn test_find_strings() {
+        let result=find_strings(b"ab\0hello\0world!\x01xyz\xc3\xa9t",4);
+        assert!(result==[(3,9,true),(9,15,false),(16,22,false)],"Actual: {:?}",result);
+    }
+
+    #[test]
+    fn test_find_strings_control() {
+        // control characters end a string, in ASCII or UTF-8
+        let result=find_strings(b"abcd\xc2\x85efgh\x7fijk",4);
+        assert!(result==[(0,4,false),(6,10,false)],"Actual: {:?}",result);
+    }
+
+    #[test]
+    fn test_fill_strings() {
+        let is=script("1 byte words\nmask 0xf0 {\n0x4 = ret class return\n}\n");
+        let br=reader(&is,b"\x40hey!\0\xff");
+        let (code,_)=decode_reached(&br,&is,&ModeMap::new(),&DataMap::new(),&[0]);
+        let all=fill_data(&br,&is,&DataMap::new(),4,code);
+        let got: Vec<(u64,&str)>=all.iter().map(|ins| (ins.addr,ins.name())).collect();
+        assert!(got==[(0,"ret"),(1,".asciz"),(6,".byte")],"Actual: {:?}",got);
+        let text: Vec<u8>=all[1].words().map(|w| w as u8).collect();
+        assert!(text==b"hey!\0","Actual: {:?}",text);
+    }
+
+    #[test]
+    fn test_set_mode() {
+        let mut modes=ModeMap::new();
+        set_mode(&mut modes,0x10,Some(0x20),1);
+        // overlapping the end of the last range, which ends earlier now
+        set_mode(&mut modes,0x18,Some(0x30),2);
+        let got: Vec<(u64,usize)>=modes.iter().map(|(a,m)| (*a,*m)).collect();
+        assert!(got==[(0x10,1),(0x18,2),(0x30,0)],"Actual: {:?}",got);
+
+        // without an end, until the next range
+        set_mode(&mut modes,0x0,None,3);
+        assert!(mode_at(&modes,0x8)==3 && mode_at(&modes,0x10)==1 && mode_at(&modes,0x40)==0,"Not in order");
+    }
+
+    #[test]
+    fn test_set_data() {
+        let show=|data: &DataMap| -> Vec<(u64,u64,&str)> {
+            data.iter().map(|(a,(e,k))| (*a,*e,k.keyword())).collect()
+        };
+
+        // over the start of a range
+        let mut data=DataMap::new();
+        set_data(&mut data,0x10,0x20,DataKind::Byte);
+        set_data(&mut data,0x18,0x28,DataKind::Word);
+        assert!(show(&data)==[(0x10,0x18,"byte"),(0x18,0x28,"word")],"Actual: {:?}",show(&data));
+
+        // inside a range, which is split around it
+        let mut data=DataMap::new();
+        set_data(&mut data,0x10,0x20,DataKind::Byte);
+        set_data(&mut data,0x14,0x18,DataKind::Half);
+        assert!(show(&data)==[(0x10,0x14,"byte"),(0x14,0x18,"half"),(0x18,0x20,"byte")],"Actual: {:?}",show(&data));
+
+        // over several ranges
+        let mut data=DataMap::new();
+        set_data(&mut data,0x10,0x14,DataKind::Byte);
+        set_data(&mut data,0x18,0x1c,DataKind::Half);
+        set_data(&mut data,0x1e,0x24,DataKind::Ascii);
+        set_data(&mut data,0x12,0x20,DataKind::Word);
+        assert!(show(&data)==[(0x10,0x12,"byte"),(0x12,0x20,"word"),(0x20,0x24,"ascii")],"Actual: {:?}",show(&data));
+        assert!(data_end(&data,0x11)==Some(0x12) && data_end(&data,0x24).is_none(),"Wrong ends");
+    }
+
+    #[test]
+    fn test_modes() {
+        // relative branches count words, which are 2 addresses apart in
+        //  the second mode
+        let is=script("1 byte words\nmask 0xff {\n0x0 = nop\n0x1 = go +1 word sbranch word 1 0:7 switch half\n}\n\
+            mode half 2 byte words\nmask 0xff00 {\n0x00 = hnop\n0x01 = back ubranch 0:7\n0x02 = fwd ibranch 0:7\n}\n");
+        let br=reader(&is,&[0x01,0x04,0x00,0x00,0xfe,0x02,0x02,0x01,0x00,0x00]);
+        let mut modes=ModeMap::new();
+        let code=match decode_file(&br,&is,&mut modes,&DataMap::new(),1) {
+            Ok(v) => v,
+            Err(why) => panic!("{}",why),
+        };
+        let spans: Vec<(u64,usize,&str)>=code.iter().map(|ins| (ins.addr,ins.mode,ins.name())).collect();
+        assert!(spans==[(0,0,"go"),(2,0,"nop"),(3,0,"nop"),(4,1,"fwd"),(6,1,"back"),(8,1,"hnop")],"Actual: {:?}",spans);
+        let targets: Vec<Option<u64>>=code.iter().map(|ins| ins.ops().find_map(|op| op.target)).collect();
+        assert!(targets==[Some(4),None,None,Some(0),Some(2),None],"Actual: {:?}",targets);
+    }
+
+    #[test]
+    fn test_resync_across_chunks() {
+        let is=script(LONG);
+        let mut seed: u32=1;
+        let mut bytes: Vec<u8>=(0..3*CHUNK_WORDS+5).map(|_| {
+            seed=seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed>>16) as u8 & 1
+        }).collect();
+        // an instruction runs from the end of the first chunk into the
+        //  second, whose first words start instructions themselves
+        let c=CHUNK_WORDS as usize;
+        bytes[c-4..c+3].copy_from_slice(&[0,0,0,1,1,1,1]);
+
+        for jobs in [1,4] {
+            let (got,want)=both_ways(&is,&reader(&is,&bytes),jobs);
+            assert!(want.contains(&(CHUNK_WORDS-1,3)),"Not across chunks");
+            assert!(got==want,"jobs {}: {} instructions, not {}",jobs,got.len(),want.len());
+        }
+    }
+
+    #[test]
+    fn test_resync_past_failure() {
+        // a chunk which fails from its first word but not from the end of
+        //  the instruction running into it
+        let is=script(LONG);
+        let mut bytes=vec![0u8; 2*CHUNK_WORDS as usize];
+        let c=CHUNK_WORDS as usize;
+        bytes[c-1..c+2].copy_from_slice(&[1,2,2]);
+
+        let (got,want)=both_ways(&is,&reader(&is,&bytes),4);
+        assert!(got==want,"{} instructions, not {}",got.len(),want.len());
+    }
+}
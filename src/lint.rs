@@ -0,0 +1,86 @@
+/*
+ * lint.rs - checks for likely mistakes in an instructions set
+ */
+use super::{
+    Instrset,
+    Maskmap,
+    Node,
+    bits as bits, bits::Word,
+};
+
+/*
+ * Everything suspicious about <is>: warnings from parsing (with their
+ *  line numbers) first, then problems found by walking the opcode tree.
+ */
+pub fn lint<W: Word>(is: &Instrset<W>) -> Vec<String> {
+    let mut ret: Vec<String>=is.warnings.iter()
+        .map(|(ln,what)| format!("Line {}: {}",ln,what))
+        .collect();
+    for m in &is.modes {
+        let mut found=Vec::new();
+        lint_map(&m.set,W::ZERO,W::ZERO,bits::low_bits(m.layout.wordbits),&mut found);
+        match is.modes.len() {
+            1 => ret.append(&mut found),
+            _ => ret.extend(found.into_iter().map(|p| format!("Mode {}: {}",m.name,p))),
+        }
+    }
+    for name in is.table_names() {
+        let mut found=Vec::new();
+        lint_map(&is.tables[name],W::ZERO,W::ZERO,bits::low_bits(is.modes[0].layout.wordbits),&mut found);
+        ret.extend(found.into_iter().map(|p| format!("Table {}: {}",name,p)));
+    }
+    ret
+}
+
+/*
+ * Helper for lint()
+ * <path> is the opcode of <set> (all parent opcodes ORed together), and
+ *  <above> is every parent mask ORed together
+ */
+fn lint_map<W: Word>(set: &Maskmap<W>, path: W, above: W, word: W, out: &mut Vec<String>) {
+    let here=above|set.mask;
+
+    if set.mask & !word != W::ZERO {
+        out.push(format!("[{:#x}] Mask {:#x} is wider than a word",path,set.mask));
+    }
+    if set.mask & above != W::ZERO {
+        out.push(format!("[{:#x}] Mask {:#x} overlaps the masks of its parents ({:#x})",
+                         path,set.mask,above));
+    }
+    if set.map.is_empty() {
+        out.push(format!("[{:#x}] Map with mask {:#x} has no entries",path,set.mask));
+    }
+
+    let mut keys: Vec<&W>=set.map.keys().collect();
+    keys.sort();
+    for k in keys { match &set.map[k] {
+        Node::Map(m) => lint_map(m,path|*k,here,word,out),
+        Node::Instr((name,ifmt)) => {
+            // operand bits seen so far, by the words they are in
+            let mut seen: Vec<((usize,usize),W)>=Vec::new();
+            for (n,f) in ifmt.fmt.iter().enumerate() {
+                let (first,last)=f.words;
+                let width=bits::low_bits::<W>(word.count_ones() as usize*(last-first+1));
+                if f.words==(0,0) && f.mask & here != W::ZERO {
+                    out.push(format!("[{:#x}] {}: operand {} ({:#x}) overlaps opcode bits ({:#x})",
+                                     path|*k,name,n+1,f.mask,here));
+                }
+                if seen.iter().any(|(w,m)| *w==f.words && f.mask & *m != W::ZERO) {
+                    out.push(format!("[{:#x}] {}: operand {} ({:#x}) overlaps an earlier operand",
+                                     path|*k,name,n+1,f.mask));
+                }
+                if f.mask & !width != W::ZERO {
+                    out.push(format!("[{:#x}] {}: operand {} ({:#x}) is wider than {}",
+                                     path|*k,name,n+1,f.mask,
+                                     match last-first {0 => "a word", _ => "its words"}));
+                }
+                seen.push((f.words,f.mask));
+            }
+            if ifmt.ext>0 && !ifmt.fmt.iter().any(|f| f.words.1==ifmt.ext) {
+                out.push(format!("[{:#x}] {}: trailing word {} is not used by any operand",
+                                 path|*k,name,ifmt.ext));
+            }
+        },
+        Node::Prefix(_) => (),
+    }}
+}
@@ -1,77 +1,481 @@
 use std::{
     env,
-    fs::File,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    process,
 };
 
 mod parse;
 use parse::instrset as instrset;
 use parse::deassemble as deassemble;
+use parse::lint as lint;
 use deassemble::branch as branch;
+use deassemble::cfg as cfg;
+use deassemble::funcs as funcs;
+use deassemble::coverage as coverage;
+use branch::ir as ir;
 
 use instrset::{
     Instrset,
     binreader::Binreader,
+    bits::Word,
 };
 
 use branch::BranchTree;
+use branch::symbols::Symbols;
+use ir::{ModeMap, DataMap};
+
+mod annot;
+
+mod cli;
+use cli::{
+    Command,
+    Options,
+    Failure,
+};
 
 
 fn main() {
-    let argv: Vec<String> = env::args().collect();
-    if argv.len() <= 1 {
-        eprintln!("Usage: {} [filename]",&argv[0]);
+    let opts = match cli::parse_args(env::args().skip(1)) {
+        Ok(o) => o,
+        Err(why) => {
+            eprintln!("{}",why);
+            process::exit(Failure::Usage.code())
+        },
+    };
+
+    if opts.cmd == Command::Help {
+        print!("{}",cli::USAGE);
         return
     }
 
-    // Make instructions set from script
-    let is: Instrset;
-    eprintln!("== Read Script ==");
-    match File::open(&argv[1]) {
-        Ok(file) => {
-            match parse::parse_file(&file) {
-                Err((t,ln)) => {
-                    eprintln!("Line {}: Syntax error in file: {}",ln,t);
-                    return
-                },
-                Ok(d) => { 
-                    is=d;
-                    eprintln!("Finished parsing file {}",&argv[1]);
-                }
-            }
+    // pick the narrowest word type that fits the script
+    let res = match fs::read_to_string(&opts.script) {
+        Ok(text) => match parse::script_wordbits(text.as_bytes()) {
+            Ok(n) if n <= u64::BITS as usize => run::<u64>(&opts,&text),
+            Ok(_) => run::<u128>(&opts,&text),
+            Err((t,ln)) => {
+                eprintln!("Line {}: Syntax error in file: {}",ln,t);
+                Err(Failure::Script)
+            },
+        },
+        Err(why) => {
+            eprintln!("Couldn't open script file {}: {}",&opts.script,why);
+            Err(Failure::IO)
+        },
+    };
+    if let Err(f) = res {
+        process::exit(f.code())
+    }
+}
+
+/*
+ * Run the command in <opts>, with words of type W
+ */
+fn run<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    match opts.cmd {
+        Command::Disasm => disasm::<W>(opts,script),
+        Command::Cfg => cfg::<W>(opts,script),
+        Command::Xrefs => xrefs::<W>(opts,script),
+        Command::Decode => decode::<W>(opts,script),
+        Command::Lint => lint::<W>(opts,script),
+        Command::Info => info::<W>(opts,script),
+        Command::DumpTree => dump_tree::<W>(opts,script),
+        Command::Help => Ok(()),
+    }
+}
 
+/*
+ * Make instructions set from the text of a script, with the settings
+ *  for decoding from <opts>
+ */
+fn read_script<W: Word>(opts: &Options, script: &str) -> Result<Instrset<W>,Failure> {
+    match parse::parse_file(script.as_bytes()) {
+        Err((t,ln)) => {
+            eprintln!("Line {}: Syntax error in file: {}",ln,t);
+            Err(Failure::Script)
+        },
+        Ok(mut d) => {
+            d.strict=opts.strict;
+            Ok(d)
         },
+    }
+}
+
+/*
+ * Open a binary file for <is>
+ */
+fn read_binary<W: Word>(path: &String, is: &Instrset<W>) -> Result<Binreader<W>,Failure> {
+    match Binreader::new(path, &is.address_layout()) {
+        Ok(br) => Ok(br),
         Err(why) => {
-            eprintln!("Couldn't open script file {}: {}",&argv[1],why);
-            return
+            eprintln!("Couldn't open binary file {}: {}",path,why);
+            Err(Failure::IO)
         },
     }
+}
 
-    if argv.len()<3 {
-        eprintln!("No binary file given; done");
-        return
+/*
+ * Modes of the code in a binary, from the annotation file and then
+ *  --mode arguments, and its ranges of data, from the script and then
+ *  the annotation file
+ */
+fn read_annotations<W: Word>(opts: &Options, is: &Instrset<W>) -> Result<(ModeMap,DataMap),Failure> {
+    let mut ann = annot::Annotations::default();
+    if let Some(path) = &opts.annotate {
+        annot::read_file(path, &mut ann).map_err(|why| {
+            eprintln!("{}",why);
+            match why {
+                annot::AnnotErr::Open(..) => Failure::IO,
+                annot::AnnotErr::Bad(_) => Failure::Usage,
+            }
+        })?;
+    }
+    let res = (|| {
+        for arg in &opts.modes {
+            ann.modes.push(annot::parse_mode_arg(arg)?);
+        }
+        Ok((annot::mode_map(&ann, is)?, annot::data_map(&ann, is)?))
+    })();
+    res.map_err(|why: String| {
+        eprintln!("{}",why);
+        Failure::Usage
+    })
+}
+
+/*
+ * Rendering settings for <is>, with the symbols from every --symbols file
+ */
+fn render_opts<W: Word>(opts: &Options, is: &Instrset<W>) -> Result<deassemble::RenderOpts,Failure> {
+    let mut symbols = Symbols::new(is.address_bytes());
+    for path in &opts.symbols {
+        match symbols.read_file(path) {
+            Ok(n) => eprintln!("Read {} symbols from {}",n,path),
+            Err(why) => {
+                eprintln!("{}",why);
+                return Err(Failure::IO)
+            },
+        }
     }
+    Ok(deassemble::RenderOpts {
+        format: opts.format,
+        modes: is.modes.iter().map(|m| (m.name.clone(),m.layout.wordbits)).collect(),
+        data_bits: is.address_layout().wordbits,
+        symbols,
+        xrefs: branch::Xrefs::new(),
+        describe: opts.describe,
+        aliases: !opts.no_aliases,
+    })
+}
 
-    // -- De-assemble a binary file --
-    // Open file
-    let mut binreader = match Binreader::new(is.wordsize, &argv[2], is.endian_little) {
-        Some(br) => br,
-        None => { return },
-    };
+/*
+ * Buffered writer to the -o file, or to stdout
+ */
+fn open_output(opts: &Options) -> Result<Box<dyn Write>,Failure> {
+    match &opts.output {
+        Some(p) => match File::create(p) {
+            Ok(f) => Ok(Box::new(BufWriter::new(f))),
+            Err(why) => {
+                eprintln!("Couldn't create output file {}: {}",p,why);
+                Err(Failure::IO)
+            },
+        },
+        None => Ok(Box::new(BufWriter::new(io::stdout().lock()))),
+    }
+}
+
+/*
+ * Report a failed write to the output
+ */
+fn write_failed(why: io::Error) -> Failure {
+    eprintln!("Couldn't write output: {}",why);
+    Failure::IO
+}
+
+/*
+ * Addresses of the --entry arguments, given as numbers or symbol names
+ */
+fn read_entries(opts: &Options, symbols: &Symbols) -> Result<Vec<u64>,Failure> {
+    let mut entries: Vec<u64> = Vec::with_capacity(opts.entries.len());
+    for arg in &opts.entries {
+        match parse::parse_number::<u64>(arg).ok().or_else(|| symbols.addr(arg)) {
+            Some(a) => entries.push(a),
+            None => {
+                eprintln!("Couldn't parse \"{}\" as an address or symbol",arg);
+                return Err(Failure::Usage)
+            },
+        }
+    }
+    Ok(entries)
+}
+
+/*
+ * Decode the binary file wrapped by <binreader>: every word once, or
+ *  with --recursive only the code reached from the entry points.
+ * With --coverage, words which fail are left as data, and the report is
+ *  written once decoded
+ */
+fn decode_binary<'a,W: Word>(opts: &Options, is: &'a Instrset<W>, symbols: &Symbols, binreader: &'a Binreader<W>)
+-> Result<Vec<ir::Instr<'a,W>>,Failure> {
+    let (mut modes, data) = read_annotations(opts, is)?;
+    let mut entries = read_entries(opts, symbols)?;
+
+    eprintln!("== Decode ==");
+    if opts.recursive {
+        entries.extend(symbols.code_labels());
+        if entries.is_empty() {entries.push(0);}
+        let (code,errs) = ir::decode_reached(binreader, is, &modes, &data, &entries);
+        for (_,why) in &errs {
+            eprintln!("Warning: {}",why);
+        }
+        eprintln!("Decoded {} instructions from {} entry points",code.len(),entries.len());
+        write_coverage(opts, is, &code, &errs)?;
+        return Ok(ir::fill_data(binreader, is, &data, opts.min_string, code))
+    }
+    if opts.coverage {
+        let (code,errs) = ir::decode_sweep(binreader, is, &modes, &data);
+        eprintln!("Decoded {} instructions, {} failed",code.len(),errs.len());
+        write_coverage(opts, is, &code, &errs)?;
+        return Ok(ir::fill_data(binreader, is, &data, opts.min_string, code))
+    }
+    match ir::decode_file(binreader, is, &mut modes, &data, opts.jobs) {
+        Ok(v) if data.is_empty() => {eprintln!("Decoded {} instructions",v.len()); Ok(v)},
+        Ok(v) => {
+            eprintln!("Decoded {} instructions around {} ranges of data",v.len(),data.len());
+            Ok(ir::fill_data(binreader, is, &data, opts.min_string, v))
+        },
+        Err(why) => {eprintln!("{}",why); Err(Failure::Decode)},
+    }
+}
+
+/*
+ * Report how much of <is> the decoded <code> uses to stderr, with the
+ *  words which failed in <errs>, if <opts> asks for it
+ */
+fn write_coverage<W: Word>(opts: &Options, is: &Instrset<W>, code: &[ir::Instr<W>], errs: &ir::ModeErrs<W>)
+-> Result<(),Failure> {
+    if !opts.coverage {return Ok(())}
+    eprintln!("== Coverage ==");
+    coverage::write_report(is, code, errs, &mut io::stderr().lock())
+        .map_err(write_failed)
+}
 
-    // find any branch labels who move upwards
+/*
+ * De-assemble a binary file
+ */
+fn disasm<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let binpath = &opts.args[0];
+
+    eprintln!("== Read Script ==");
+    let is = read_script::<W>(opts, script)?;
+    eprintln!("Finished parsing file {}",&opts.script);
+
+    let mut ropts = render_opts(opts, &is)?;
+    let binreader = read_binary(binpath, &is)?;
+    let mut out = open_output(opts)?;
+    let instrs = decode_binary(opts, &is, &ropts.symbols, &binreader)?;
+
+    // find all branch labels
     eprintln!("== Generate Branch Labels ==");
-    let mut branches: BranchTree = BranchTree::new();
-    match branch::add_branch_ups(&mut binreader,&mut branches, &is.set) {
-        Ok(()) => {eprintln!("Generated branches for file {}",argv[2]);},
-        Err(why) => {eprintln!("{}",why); return},
-    };
-        
+    let mut branches: BranchTree = branch::gen_labels(&instrs);
+    ropts.xrefs = branch::gen_xrefs(&instrs, ropts.symbols.unit);
+    // every named address gets a label too
+    branches.extend(ropts.symbols.labels());
+    eprintln!("Generated branches for file {}",binpath);
 
     // deassemble
     eprintln!("== Deassemble ==");
-    match deassemble::deassemble_file(&mut binreader,&is,&mut branches) {
-        Ok(()) => { eprintln!("Done reading file {}",argv[2]); },
-        Err(why) => {eprintln!("{}",why); return}
+    if opts.functions {
+        let mut entries = funcs::call_targets(&instrs);
+        entries.extend(ropts.symbols.code_labels());
+        entries.extend(read_entries(opts, &ropts.symbols)?);
+        let entries: Vec<u64> = entries.into_iter().collect();
+        let blocks = cfg::blocks(&instrs, &entries);
+        let found = funcs::find(&instrs, &blocks, &entries);
+        funcs::render_grouped(&instrs,&blocks,&found,&branches,&ropts,&mut out)
+            .map_err(write_failed)?;
+
+        eprintln!("== Functions ==");
+        funcs::write_summary(&found,&ropts,&mut io::stderr().lock())
+            .map_err(write_failed)?;
+    }
+    else {
+        deassemble::render_all(&instrs,&branches,&ropts,opts.jobs,&mut out)
+            .map_err(write_failed)?;
     }
+
+    if let Some(path) = &opts.export_symbols {
+        let kinds = branch::label_kinds(&instrs);
+        File::create(path)
+            .and_then(|f| ropts.symbols.export(&branches,&kinds,&mut BufWriter::new(f)))
+            .map_err(|why| {
+                eprintln!("Couldn't write symbol file {}: {}",path,why);
+                Failure::IO
+            })?;
+        eprintln!("Wrote {} labels to {}",branches.len(),path);
+    }
+    eprintln!("Done reading file {}",binpath);
+    Ok(())
+}
+
+/*
+ * Write the control flow graph of a binary file as DOT
+ */
+fn cfg<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let ropts = render_opts(opts, &is)?;
+
+    let entries = read_entries(opts, &ropts.symbols)?;
+    let binreader = read_binary(&opts.args[0], &is)?;
+    let mut out = open_output(opts)?;
+    let mut instrs = decode_binary(opts, &is, &ropts.symbols, &binreader)?;
+    instrs.retain(|ins| ins.data.is_none());
+
+    eprintln!("== Find Basic Blocks ==");
+    let blocks = cfg::blocks(&instrs, &entries);
+    eprintln!("Found {} basic blocks",blocks.len());
+    cfg::write_dot(&instrs,&blocks,&entries,&ropts,&mut out)
+        .map_err(write_failed)
 }
 
+/*
+ * List the references to every address in a binary file
+ */
+fn xrefs<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let ropts = render_opts(opts, &is)?;
+    let binreader = read_binary(&opts.args[0], &is)?;
+    let mut out = open_output(opts)?;
+    let instrs = decode_binary(opts, &is, &ropts.symbols, &binreader)?;
+
+    eprintln!("== Find References ==");
+    let xrefs = branch::gen_xrefs(&instrs, ropts.symbols.unit);
+    eprintln!("Found references to {} addresses",xrefs.labels().count());
+    branch::write_xrefs(&xrefs,&ropts.symbols,&mut out)
+        .map_err(write_failed)
+}
+
+/*
+ * Decode words given on the command line, as if they were consecutive
+ *  words of a binary
+ */
+fn decode<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let (modes, _) = read_annotations(opts, &is)?;
+    let ropts = render_opts(opts, &is)?;
+    let mut out = open_output(opts)?;
+    let mut ret = Ok(());
+
+    let mut words: Vec<W> = Vec::with_capacity(opts.args.len());
+    for arg in &opts.args {
+        match parse::parse_number(arg) {
+            Ok(w) => words.push(w),
+            Err(why) => {
+                eprintln!("Couldn't parse \"{}\" as a number: {}",arg,why);
+                return Err(Failure::Usage)
+            },
+        }
+    }
+
+    // <k> words are used up by the instructions before address <i>
+    let (mut i, mut k) = (0, 0);
+    let mut prev = None;
+    while k < words.len() {
+        let mode = ir::mode_at(&modes, i);
+        let unit = is.unit(mode);
+        let given = ir::Given {words: &words, first: k, addr: i};
+        match ir::decode(&given, i, mode, &is) {
+            Ok(ins) => {
+                i += ins.len;
+                k += (ins.len/unit) as usize;
+                let mut s = String::new();
+                deassemble::render(&[ins],&BranchTree::new(),&ropts,prev,&mut s);
+                out.write_all(s.as_bytes()).map_err(write_failed)?;
+            },
+            Err(why) => {
+                eprintln!("{}",why);
+                ret = Err(Failure::Decode);
+                i += unit;
+                k += 1;
+            },
+        }
+        prev = Some(mode);
+    }
+    out.flush().map_err(write_failed)?;
+    ret
+}
+
+/*
+ * Report likely mistakes in a script
+ */
+fn lint<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let mut out = open_output(opts)?;
+
+    let problems = lint::lint(&is);
+    for p in &problems {
+        writeln!(out,"{}: {}",opts.script,p).map_err(write_failed)?;
+    }
+    out.flush().map_err(write_failed)?;
+    match problems.is_empty() {
+        true  => Ok(()),
+        false => Err(Failure::Script),
+    }
+}
+
+/*
+ * Describe a script, and a binary if given
+ */
+fn info<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let mut out = open_output(opts)?;
+
+    let (mut n_instrs,mut n_maps,mut depth) = (0,0,0);
+    for t in is.modes.iter().map(|m| &m.set).chain(is.tables.values()) {
+        let (i,m,d) = instrset::count_nodes(t);
+        n_instrs += i;
+        n_maps += m;
+        depth = depth.max(d);
+    }
+    writeln!(out,"Script:       {}",opts.script).map_err(write_failed)?;
+    for m in &is.modes {
+        if is.modes.len() > 1 {
+            writeln!(out,"Mode:         {}",m.name).map_err(write_failed)?;
+        }
+        writeln!(out,"Layout:       {}",m.layout)
+            .and_then(|()| writeln!(out,"Byte order:   {}{} endian",
+                                    match &m.layout.byte_order {
+                                        Some(order) => format!("bytes {:?} then ",order),
+                                        None => String::new(),
+                                    },
+                                    match m.layout.endian_little {true=>"little", false=>"big"}))
+            .map_err(write_failed)?;
+    }
+    writeln!(out,"Instructions: {}",n_instrs)
+        .and_then(|()| writeln!(out,"Maps:         {} (up to {} deep)",n_maps,depth))
+        .and_then(|()| writeln!(out,"Tables:       {}",is.tables.len()+is.modes.len()))
+        .map_err(write_failed)?;
+
+    if let Some(binpath) = opts.args.first() {
+        let br = read_binary(binpath, &is)?;
+        writeln!(out,"Binary:       {}",binpath)
+            .and_then(|()| writeln!(out,"Size:         {} bytes",br.size()))
+            .and_then(|()| match is.modes.len() {
+                1 => writeln!(out,"Words:        {}",br.n_instrs),
+                _ => Ok(()),
+            })
+            .map_err(write_failed)?;
+    }
+    out.flush().map_err(write_failed)
+}
+
+/*
+ * Print the opcode tree of a script
+ */
+fn dump_tree<W: Word>(opts: &Options, script: &str) -> Result<(),Failure> {
+    let is = read_script::<W>(opts, script)?;
+    let mut out = open_output(opts)?;
+    instrset::dump_tree(&is,&mut out)
+        .and_then(|()| out.flush())
+        .map_err(write_failed)
+}
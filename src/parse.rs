@@ -1,8 +1,6 @@
 use std::{
     fmt::Display,
-    fs::File,
-    io::{self, BufRead},
-    u64,
+    io::BufRead,
     num::ParseIntError,
     collections::{
         HashMap,
@@ -13,29 +11,53 @@ use std::{
 #[path="deassemble.rs"]
 pub mod deassemble;
 
+#[path="lint.rs"]
+pub mod lint;
+
+#[cfg(test)]
+#[path="tests.rs"]
+mod tests;
+
 pub use deassemble::instrset::{
     self as instrset,
     Instrfmt,
     Fmt, FmtType,
-    Node,
+    Node, Prefix, Switch, Class, Alias,
+    DataKind, DataRange,
+    Mode,
     Maskmap,
     Instrset,
+    binreader::Layout,
     bits as bits, bits::{
-        Wordt,
-        Bitmask,
+        Word,
         BitOp, BitOpType,
     },
 };
 
+// most trailing words an instruction can have
+pub const MAX_TRAILING_WORDS: usize=64;
 
 pub enum ErrType {
     NoWordsize(String),
     NoMask(String),
     ZeroWordsize,
+    WordsizeTooLarge(usize),
+    BadLayout(String),
     NoWordsizeUnits(String),
     ZeroMask(String),
     ParseNumber(String,ParseIntError),
     ExtraClosingBrace,
+    ExpectedTable(String),
+    UnknownTable(String),
+    UnknownMode(String),
+    BadSwitch(String),
+    UnknownClass(String),
+    BadOperandWords(String),
+    BadData(String),
+    BadDescription(String),
+    BadAlias(String),
+    BadReserved(String),
+    UnknownInstr(String),
     Internal(std::io::Error),
 
     UnknownFormat(String),
@@ -62,6 +84,12 @@ impl Display for ErrType {
             ErrType::ZeroWordsize =>
                 write!(f,"Word size cannot be 0"),
 
+            ErrType::WordsizeTooLarge(n) =>
+                write!(f,"Word size ({} bits) is larger than the maximum of {} bits",
+                n, bits::MAX_WORD_BITS),
+
+            ErrType::BadLayout(why) => write!(f,"{}",why),
+
             ErrType::NoWordsizeUnits(num) =>
                 write!(f,"Wordsize was not given units; add \"bytes\" after: {}",num),
 
@@ -77,6 +105,54 @@ impl Display for ErrType {
 
             ErrType::ExtraClosingBrace => write!(f,"Extra closing brace"),
 
+            ErrType::ExpectedTable(found) =>
+                write!(f,"Expected a table (like \"table cb mask 0xff {{\"), \
+                       mode (like \"mode thumb 2 byte words\") \
+                       or data (like \"data 0x100:0x200 word\") \
+                       after the instruction set. Found:\n{}",
+                found),
+
+            ErrType::BadOperandWords(why) => write!(f,"{}",why),
+
+            ErrType::BadDescription(found) =>
+                write!(f,"Expected one description in quotes, \
+                       like \"Add immediate to register\", \
+                       at the end of an instruction line. Found:\n{}",
+                found),
+
+            ErrType::BadAlias(why) => write!(f,"{}",why),
+
+            ErrType::BadReserved(found) =>
+                write!(f,"Expected reserved bits and the value they must have, \
+                       like \"reserved bits 20:24 = 0\" or \"reserved mask 0x1f00000 = 0x1f\". Found:\n{}",
+                found),
+
+            ErrType::UnknownInstr(name) =>
+                write!(f,"Alias refers to instruction \"{}\", which is never declared",name),
+
+            ErrType::BadData(found) =>
+                write!(f,"Expected a range and a kind of data \
+                       (byte, half, word, ascii or skip) \
+                       like \"data 0x100:0x200 word\". Found:\n{}",
+                found),
+
+            ErrType::UnknownTable(name) =>
+                write!(f,"Prefix refers to table \"{}\", which is never declared",name),
+
+            ErrType::UnknownMode(name) =>
+                write!(f,"Switch refers to mode \"{}\", which is never declared",name),
+
+            ErrType::UnknownClass(class) =>
+                write!(f,"Unrecognized class: \"{}\" \
+                       (expected call, jump, cbranch, return or halt)",
+                class),
+
+            ErrType::BadSwitch(found) =>
+                write!(f,"Expected a mode switch \
+                       (like \"switch thumb\" or \"switch thumb if odd\") \
+                       at the end of the line. Found:\n{}",
+                found),
+
             ErrType::Internal(why) => write!(f,"I/O error: {}",why),
 
             ErrType::UnknownFormat(fmt) =>
@@ -95,7 +171,7 @@ impl Display for ErrType {
  * Only to be called in error cases!
  * Concatenate all strings in <words> to a String
  */
-fn wordsvec_to_string(words: &Vec<&str>) -> String {
+fn wordsvec_to_string(words: &[&str]) -> String {
     let mut ret=String::new();
     for word in words {
         ret+=word;
@@ -109,10 +185,10 @@ fn wordsvec_to_string(words: &Vec<&str>) -> String {
 /*
  * String to int
  */
-fn parse_number(text: &str) -> Result<Wordt, ParseIntError> {
-    if let Some(s)=text.strip_prefix("0b")      {return Wordt::from_str_radix(s,2)}
-    else if let Some(s)=text.strip_prefix("0x") {return Wordt::from_str_radix(s,16)}
-    else                                        {return Wordt::from_str_radix(text,10)}
+pub fn parse_number<W: Word>(text: &str) -> Result<W, ParseIntError> {
+    if let Some(s)=text.strip_prefix("0b")      {W::from_str_radix(s,2)}
+    else if let Some(s)=text.strip_prefix("0x") {W::from_str_radix(s,16)}
+    else                                        {W::from_str_radix(text,10)}
 }
 
 /*
@@ -122,9 +198,9 @@ fn parse_number(text: &str) -> Result<Wordt, ParseIntError> {
  *  or      3   -> bit 3          -> 0b00001000
  * TODO more descriptive errors; ParseRangeErr type?
  */
-fn parse_range(text: &str) -> Option<Bitmask> {
-    let mut range: [Wordt; 2]=[0,0];
-    let mut ret: Bitmask=0;
+fn parse_range<W: Word>(text: &str) -> Option<W> {
+    let mut range: [usize; 2]=[0,0];
+    let mut ret: W=W::ZERO;
 
     // range
     if text.contains(':') {
@@ -134,26 +210,25 @@ fn parse_range(text: &str) -> Option<Bitmask> {
          */
         for (i,num) in text.split(':').enumerate() {
             if i>1 {return None}
-            range[i]=match parse_number(num) {
-                Ok(x) => x,
-                Err(_) => {return None}
+            range[i]=match parse_number::<u64>(num) {
+                Ok(x) if x<W::BITS as u64 => x as usize,
+                _other => {return None}
             };
             /*
              * Generate bitmask from [a,b]
              * i.e. range=[3,5] => ret=0b00111000
              */
-            if i==1 {ret= ((1<<range[0])-1)
-                         ^((1<<range[1])-1)
-                         +(1<<range[1]);
+            if i==1 {ret= bits::low_bits::<W>(range[0])
+                         ^bits::low_bits::<W>(range[1]+1);
             }
         }
-        return Some(ret);
+        Some(ret)
     }
     // single bit
     else {
-        match parse_number(text) {
-            Ok(x) => {return Some(1<<x)},
-            Err(_) => {return None}
+        match parse_number::<u64>(text) {
+            Ok(x) if x<W::BITS as u64 => Some(W::ONE<<x as u32),
+            _other => None,
         }
     }
 }
@@ -163,14 +238,14 @@ fn parse_range(text: &str) -> Option<Bitmask> {
  *  mask 0b01001
  * Returns the bitmask and the number of words read
  */
-fn gen_mask(v: &Vec<&str>, start: usize, reverse: usize) -> Option<(Bitmask,usize)> {
-    if v.len()-1<start {return None}
-    let mut mask: Bitmask=0;
+fn gen_mask<W: Word>(v: &[&str], start: usize, reverse: usize) -> Option<(W,usize)> {
+    if v.len()<=start {return None}
+    let mut mask: W=W::ZERO;
 
     // look for mask [mask]
     if v[start]=="mask" {
-        if let Ok(n)=parse_number(v[start+1]) {
-            if n==0 {return None}
+        if let Ok(n)=parse_number::<W>(v.get(start+1)?) {
+            if n==W::ZERO {return None}
 
             mask=n;
             if reverse>0 {mask=bits::reverse(&mask,reverse);}
@@ -180,10 +255,7 @@ fn gen_mask(v: &Vec<&str>, start: usize, reverse: usize) -> Option<(Bitmask,usiz
     // look for bits [range]
     else {
         for range in v[start].split('+') {
-            mask |= match parse_range(range) {
-                Some(m) => m,
-                None => {return None}
-            }
+            mask |= parse_range(range)?
         }
         if reverse>0 {mask=bits::reverse(&mask,reverse);}
         return Some((mask,1))
@@ -193,80 +265,360 @@ fn gen_mask(v: &Vec<&str>, start: usize, reverse: usize) -> Option<(Bitmask,usiz
 }
 
 /*
- * Read wordsize and endianness
+ * Read word layout and the reversed flag from a line like:
+ *  4 byte [reversed] [native endian|nonnative endian] words
+ *  14 bit [...] [checked] words in 2 byte containers
+ *  12 bit [...] packed words
  */
-fn parse_first_line(words: &Vec<&str>) -> Result<(usize,bool,bool),ErrType> {
-    let mut native_endian: bool=true;
+fn parse_first_line(words: &[&str]) -> Result<(Layout,bool),ErrType> {
+    let mut layout=Layout {wordbits: 0, container: 0, endian_little: true, check_padding: false, byte_order: None};
     let mut reversed: bool=false;
-    let wordsize: usize;
-
-    if (words.len()>=3 && words.len()<=6) && "words"==words[words.len()-1] {
-        // wordsize from first 2 words
-        match parse_number(&words[0]) {
-            Ok(n) => {
-                if n==0                  {return Err(ErrType::ZeroWordsize)}
-                else if words[1]=="byte" {wordsize=n.try_into().unwrap()}
-                else {return Err(ErrType::NoWordsizeUnits(words[0].to_string()))}
+    let mut packed: bool=false;
+
+    // "words" ends the declaration, possibly followed by containers
+    let end=match words.iter().position(|w| *w=="words") {
+        Some(i) if i>=2 => i,
+        _other => {return Err(ErrType::NoWordsize(wordsvec_to_string(words)))},
+    };
+
+    // wordsize from first 2 words
+    let n=match parse_number::<u64>(words[0]) {
+        Ok(0) => {return Err(ErrType::ZeroWordsize)},
+        Ok(n) => n as usize,
+        Err(why) => {return Err(ErrType::ParseNumber(words[0].to_string(),why))},
+    };
+    let in_bytes=match words[1] {
+        "byte" | "bytes" => true,
+        "bit" | "bits" => false,
+        _other => {return Err(ErrType::NoWordsizeUnits(words[0].to_string()))},
+    };
+
+    // check remaining words for reversed flag, endianness and padding
+    let mut i=2; // number of words already read
+    while i<end {
+        if words[i]=="reversed" {
+            reversed=true;
+        }
+        else if words[i]=="nonnative" && i+1<end && words[i+1]=="endian" {
+            i+=1;
+            layout.endian_little=false;
+        }
+        else if words[i]=="native" && i+1<end && words[i+1]=="endian" {
+            // do nothing
+            i+=1;
+        }
+        else if words[i]=="packed" && !in_bytes {
+            packed=true;
+        }
+        else if words[i]=="checked" {
+            layout.check_padding=true;
+        }
+        else {
+            return Err(ErrType::NoWordsize(wordsvec_to_string(words)))
+        }
+        i+=1;
+    }
+
+    // containers
+    let rest=&words[end+1..];
+    if !rest.is_empty() {
+        if rest.len()!=4 || rest[0]!="in" || !matches!(rest[2],"byte"|"bytes") || rest[3]!="containers" {
+            return Err(ErrType::NoWordsize(wordsvec_to_string(words)))
+        }
+        layout.container=match parse_number::<u64>(rest[1]) {
+            Ok(c) => c as usize,
+            Err(why) => {return Err(ErrType::ParseNumber(rest[1].to_string(),why))},
+        };
+    }
+
+    // sizes too large to count in bits are too large for any word
+    let too_large=|n: usize, what: &str| ErrType::BadLayout(
+        format!("{} byte {} are larger than the maximum of {} bits",n,what,bits::MAX_WORD_BITS));
+    layout.wordbits=match in_bytes {
+        true => n.checked_mul(8).ok_or_else(|| too_large(n,"words"))?,
+        false => n,
+    };
+    if layout.container.checked_mul(8).is_none() {return Err(too_large(layout.container,"containers"))}
+    if layout.container==0 && !packed {
+        // whole bytes need no container
+        if !layout.wordbits.is_multiple_of(8) {return Err(ErrType::BadLayout(
+            format!("{} bit words need \"packed\" or \"in <n> byte containers\"",n)))}
+        layout.container=layout.wordbits/8;
+    }
+    else if packed && layout.container!=0 {
+        return Err(ErrType::BadLayout("Packed words cannot also have containers".to_string()))
+    }
+    else if layout.container!=0 && layout.wordbits>8*layout.container {
+        return Err(ErrType::BadLayout(
+            format!("{} bit words do not fit in {} byte containers",layout.wordbits,layout.container)))
+    }
+    if layout.check_padding && layout.wordbits==8*layout.container {
+        return Err(ErrType::BadLayout("\"checked\" needs containers wider than the words".to_string()))
+    }
+    if layout.storage_bits()>bits::MAX_WORD_BITS {
+        return Err(ErrType::WordsizeTooLarge(layout.storage_bits()))
+    }
+
+    Ok((layout,reversed))
+}
+
+/*
+ * Split a quoted description like "Add immediate to register" off the
+ *  end of <line>. In it \" stands for a quote and \\ for a backslash
+ */
+fn split_description(line: &str) -> Result<(&str,Option<String>),ErrType> {
+    let Some(open)=line.find('"') else {return Ok((line,None))};
+    let mut desc=String::new();
+    let mut chars=line[open+1..].char_indices();
+    while let Some((k,c))=chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_,c)) => desc.push(c),
+                None => break,
             },
-            Err(why) => { return Err(ErrType::ParseNumber(words[0].to_string(),why)) }
+            '"' if line[open+k+2..].trim().is_empty() => {return Ok((&line[..open],Some(desc)))},
+            '"' => break,
+            c => desc.push(c),
         }
+    }
+    Err(ErrType::BadDescription(line.trim().to_string()))
+}
 
-        // check remaining words for reversed flag and endianness
-        let mut i=2; // number of words already read
-        while i<words.len()-1 {
-            if words[i]=="reversed" {
-                reversed=true;
-            }
-            else if words[i]=="nonnative" && words.len()>i+1 && words[i+1]=="endian" {
-                i+=1;
-                native_endian=false;
-            }
-            else if words[i]=="native" && words.len()>i+1 && words[i+1]=="endian" {
-                // do nothing
-                i+=1;
-            }
-            else {
-                return Err(ErrType::NoWordsize(wordsvec_to_string(words)))
-            }
+/*
+ * Read an alias line like:
+ *  alias mv = addi if 3=0 ops 1 2
+ * for the instruction it names: conditions on its operands after "if",
+ *  and the operands to print after "ops", numbered from 1
+ */
+fn parse_alias(words: &[&str]) -> Result<(String,Alias),ErrType> {
+    let bad=|why: &str| ErrType::BadAlias(format!("{} (like \"alias mv = addi if 3=0 ops 1 2\"). Found:\n{}",
+                                                  why,wordsvec_to_string(words)));
+    let (name,instr)=match words {
+        [_,name,"=",instr,..] => (name.to_string(),instr.to_string()),
+        _ => return Err(bad("Expected an alias name, \"=\" and an instruction")),
+    };
+    let op=|w: &str| match parse_number::<u64>(w) {
+        Ok(n) if n>0 => Ok(n as usize-1),
+        _other => Err(bad("Operands are numbered from 1")),
+    };
+
+    let mut alias=Alias {name, conds: Vec::new(), ops: Vec::new()};
+    let mut i=4;
+    if words.get(i)==Some(&"if") {
+        i+=1;
+        while let Some((k,v))=words.get(i).and_then(|w| w.split_once('=')) {
+            let v=match v.strip_prefix('-') {
+                Some(n) => parse_number::<u64>(n).map(|n| -(n as i128)),
+                None => parse_number::<u64>(v).map(|n| n as i128),
+            }.map_err(|why| ErrType::ParseNumber(v.to_string(),why))?;
+            alias.conds.push((op(k)?,v));
             i+=1;
         }
-        return Ok((wordsize,native_endian,reversed))
+        if alias.conds.is_empty() {return Err(bad("Expected conditions like 3=0 after \"if\""))}
+    }
+    if words.get(i)==Some(&"ops") {
+        for w in &words[i+1..] {alias.ops.push(op(w)?);}
+        i=words.len();
+    }
+    if i<words.len() {return Err(bad("Expected \"if\" or \"ops\""))}
+    Ok((instr,alias))
+}
+
+/*
+ * Give every instruction in <set> its aliases from <aliases>, most
+ *  specific first, marking in <used> the aliases given to some
+ *  instruction. Fails with the index in <aliases> of an alias naming an
+ *  operand its instruction does not have
+ */
+fn attach_aliases<W: Word>(set: &mut Maskmap<W>, aliases: &[(String,Alias)], used: &mut [bool]) -> Result<(),usize> {
+    for n in set.map.values_mut() { match n {
+        Node::Map(m) => attach_aliases(m,aliases,used)?,
+        Node::Instr((name,ifmt)) => {
+            for (k,(_,a)) in aliases.iter().enumerate().filter(|(_,(i,_))| i==name) {
+                if a.conds.iter().map(|c| &c.0).chain(&a.ops).any(|op| *op>=ifmt.fmt.len()) {return Err(k)}
+                used[k]=true;
+                ifmt.aliases.push(a.clone());
+            }
+            ifmt.aliases.sort_by_key(|a| std::cmp::Reverse(a.conds.len()));
+        },
+        Node::Prefix(_) => (),
+    }}
+    Ok(())
+}
+
+/*
+ * Read a range of data like "0x100:0x200" of the kind named <kind>
+ */
+pub fn parse_data_range(range: &str, kind: &str) -> Result<DataRange,ErrType> {
+    let bad=|| ErrType::BadData(format!("{} {}",range,kind));
+    let Some((a,b))=range.split_once(':') else {return Err(bad())};
+    let num=|t: &str| parse_number::<u64>(t).map_err(|why| ErrType::ParseNumber(t.to_string(),why));
+    let (start,end)=(num(a)?,num(b)?);
+    match DataKind::from_keyword(kind) {
+        Some(kind) if start<end => Ok(DataRange {start, end, kind}),
+        _other => Err(bad()),
+    }
+}
+
+/*
+ * Read a byte order permutation for <layout> from a line like:
+ *  byte order 1 0 3 2
+ * which must name every byte of a container exactly once
+ */
+fn parse_byte_order(words: &[&str], layout: &mut Layout) -> Result<(),ErrType> {
+    if words.len()<3 || words[1]!="order" {return Err(ErrType::Other)}
+    if layout.container==0 {
+        return Err(ErrType::BadLayout("Packed words cannot have a byte order".to_string()))
+    }
+
+    let mut order: Vec<usize>=Vec::with_capacity(words.len()-2);
+    for w in &words[2..] {
+        match parse_number::<u64>(w) {
+            Ok(n) => {order.push(n as usize);},
+            Err(why) => {return Err(ErrType::ParseNumber(w.to_string(),why))},
+        }
+    }
+
+    let mut seen=order.clone();
+    seen.sort();
+    if !seen.iter().copied().eq(0..layout.container) {
+        return Err(ErrType::BadLayout(format!(
+            "Byte order must list each of the {} bytes of a container once, from 0",
+            layout.container)))
     }
-    Err(ErrType::NoWordsize(wordsvec_to_string(words)))
+    layout.byte_order=Some(order);
+    Ok(())
 }
 
 /*
  * Read initial mask for the instruction set
  */
-fn parse_second_line(words: &Vec<&str>, map: &mut Maskmap, reverse: usize) -> Result<(),ErrType> {
+fn parse_second_line<W: Word>(words: &[&str], map: &mut Maskmap<W>, reverse: usize) -> Result<(),ErrType> {
     if let Some( (n,_) )=gen_mask(words,0, reverse) {
-        if n==0 {return Err(ErrType::ZeroMask(words[0].to_string()))}
+        if n==W::ZERO {return Err(ErrType::ZeroMask(words[0].to_string()))}
 
         map.mask=n;
-        return Ok(())
+        Ok(())
+    }
+    else {Err(ErrType::NoMask(wordsvec_to_string(words)))}
+}
+
+/*
+ * Read the words an operand is in, after "word" or "words":
+ *  1   -> (1,1)
+ *  1:2 -> (1,2)
+ */
+fn parse_span(text: &str) -> Option<(usize,usize)> {
+    let (a,b)=text.split_once(':').unwrap_or((text,text));
+    match (parse_number::<u64>(a),parse_number::<u64>(b)) {
+        (Ok(a),Ok(b)) if a<=b => Some((a as usize,b as usize)),
+        _other => None,
     }
-    else {return Err(ErrType::NoMask(wordsvec_to_string(words)))}
 }
 
 /*
  * Create an Instrfmt
+ * The instruction may start with the number of trailing words it has,
+ *  like "+2 words", and operands in them say which, like "word 1" or
+ *  "words 1:2"; word 0 is the opcode word.
+ * A class may be given among the operands, like "class call".
+ * It may end with a mode switch, like "switch thumb if odd".
  * Must not be called on an empty line.
  */
-fn create_fmt(words: &Vec<&str>, mut start: usize, reverse: usize) 
--> Result<Instrfmt,ErrType> {
-    let mut fmt: Vec<Fmt>=Vec::new();
-    let mut mask: Bitmask;
+fn create_fmt<W: Word>(words: &[&str], mut start: usize, reverse: usize, wordbits: usize)
+-> Result<Instrfmt<W>,ErrType> {
+    let mut fmt: Vec<Fmt<W>>=Vec::new();
+    let mut mask: W;
     let mut read: usize;
-
-    let mut ops: LinkedList<BitOp>;
-    let mut n: Wordt;
+    let mut span: (usize,usize);
+    let mut ext: usize=0;
+    let mut class: Option<Class>=None;
+    let mut switch: Option<Switch>=None;
+    let mut reserved: (W,W)=(W::ZERO,W::ZERO);
+
+    let mut ops: LinkedList<BitOp<W>>;
+    let mut n: W;
     let mut tmp: BitOpType;
 
+    // trailing words
+    if let Some(num)=words.get(start).and_then(|w| w.strip_prefix('+')) {
+        ext=match parse_number::<u64>(num) {
+            Ok(x) if x<=MAX_TRAILING_WORDS as u64 => x as usize,
+            Ok(x) => {return Err(ErrType::BadOperandWords(
+                format!("{} trailing words are more than the maximum of {}",x,MAX_TRAILING_WORDS)))},
+            Err(why) => {return Err(ErrType::ParseNumber(num.to_string(),why))},
+        };
+        if !matches!(words.get(start+1), Some(&"word") | Some(&"words")) {
+            return Err(ErrType::BadOperandWords(format!("Expected \"words\" after {}",words[start])))
+        }
+        start+=2;
+    }
+
     while start<words.len() {
+        // mode switch, which ends the line
+        if words[start]=="switch" {
+            switch=match &words[start+1..] {
+                [mode] => Some(Switch {mode: mode.to_string(), odd: false}),
+                [mode,"if","odd"] => Some(Switch {mode: mode.to_string(), odd: true}),
+                _other => {return Err(ErrType::BadSwitch(wordsvec_to_string(&words[start..])))},
+            };
+            break
+        }
+
+        // class
+        if words[start]=="class" {
+            class=match words.get(start+1) {
+                Some(c) => match Class::from_keyword(c) {
+                    Some(c) => Some(c),
+                    None => {return Err(ErrType::UnknownClass(c.to_string()))},
+                },
+                None => {return Err(ErrType::UnknownClass(String::new()))},
+            };
+            start+=2;
+            continue
+        }
+
+        // bits of the opcode word with fixed values, as
+        //  "reserved bits 20:24 = 0" or "reserved mask 0x1f00000 = 0"
+        if words[start]=="reserved" {
+            let at=match words.get(start+1) {
+                Some(&"bits") => start+2,
+                _other => start+1,
+            };
+            let bad=|| ErrType::BadReserved(wordsvec_to_string(&words[start..]));
+            let (m,i)=gen_mask::<W>(words,at,reverse).ok_or_else(bad)?;
+            if words.get(at+i)!=Some(&"=") {return Err(bad())}
+            let v=match words.get(at+i+1).map(|w| parse_number::<W>(w)) {
+                Some(Ok(v)) => v,
+                Some(Err(why)) => {return Err(ErrType::ParseNumber(words[at+i+1].to_string(),why))},
+                None => {return Err(bad())},
+            };
+            if v & !bits::low_bits::<W>(m.count_ones() as usize) != W::ZERO {return Err(bad())}
+            reserved=(reserved.0|m, reserved.1|bits::align(v,m));
+            start=at+i+2;
+            continue
+        }
+
+        // words the operand is in
+        span=(0,0);
+        let mut at=start+1;
+        if matches!(words.get(at), Some(&"word") | Some(&"words")) {
+            span=match words.get(at+1).and_then(|w| parse_span(w)) {
+                Some(sp) => sp,
+                None => {return Err(ErrType::BadOperandWords(
+                    format!("Expected a word number (like 1) or range (like 1:2) after \"{}\"",words[at])))},
+            };
+            if span.1>ext {return Err(ErrType::BadOperandWords(
+                format!("Operand is in word {}, but the instruction has {} trailing words",span.1,ext)))}
+            if (span.1-span.0+1)*wordbits>W::BITS as usize {return Err(ErrType::BadOperandWords(
+                format!("Words {}:{} together are wider than {} bits",span.0,span.1,W::BITS)))}
+            at+=2;
+        }
+
         // gen mask
-        match gen_mask(words,start+1, reverse) {
-            Some( (x,i) ) => {mask=x; read=i;},
-            None => {return Err(ErrType::NoMask(words[start+1].to_string()))}
+        match gen_mask(words,at, reverse*(span.1-span.0+1)) {
+            Some( (x,i) ) => {mask=x; read=at-start-1+i;},
+            None => {return Err(ErrType::NoMask(wordsvec_to_string(&words[start..])))}
         }
 
         // get BitOps
@@ -300,43 +652,33 @@ fn create_fmt(words: &Vec<&str>, mut start: usize, reverse: usize)
 
         // get format type
         fmt.push(Fmt
-           {typ: match words[start] {
-                "addr" => FmtType::Addr,
-                "uint" => FmtType::Unsigned,
-                "int"  => FmtType::Signed,
-                "bin"  => FmtType::Binary,
-
-                "ubranch" => FmtType::Ubranch,
-                "dbranch" => FmtType::Dbranch,
-                "ibranch" => FmtType::Ibranch,
-                "sbranch" => FmtType::Sbranch,
-
-                "ignore" => FmtType::Ignore,
-
-                other => {
-                    return Err(ErrType::UnknownFormat(other.to_string()))
+           {typ: match FmtType::from_keyword(words[start]) {
+                Some(t) => t,
+                None => {
+                    return Err(ErrType::UnknownFormat(words[start].to_string()))
                 },
             },
-                mask: mask,
-                ops: ops,
+                mask,
+                words: span,
+                ops,
            }
         );
 
         start += read+1;
     }
 
-    Ok(Instrfmt {fmt})
+    Ok(Instrfmt {fmt, ext, class, switch, desc: None, aliases: Vec::new(), reserved, id: 0})
 }
 
 /*
  * Create either a Instrfmt or a Maskmap, which is returned and to be
  *  inserted into a Maskmap
  */
-fn create_node(words: &Vec<&str>,mask: Bitmask,reverse: usize) -> Result<(Wordt,Node),ErrType> {
+fn create_node<W: Word>(words: &[&str],mask: W,reverse: usize,wordbits: usize) -> Result<(W,Node<W>),ErrType> {
     if words.len()<3 {return Err(ErrType::Other)}
 
     // n Will store the opcode for the new Node, under the containing Maskmap's mask
-    let n: Wordt;
+    let n: W;
     match parse_number(words[0]) {
         Ok(x) => {n=bits::align(x,mask);},
         Err(why) => {
@@ -344,9 +686,17 @@ fn create_node(words: &Vec<&str>,mask: Bitmask,reverse: usize) -> Result<(Wordt,
         }
     }
 
+    // prefix, as "<opcode> prefix <table>" or "<opcode> = <name> prefix <table>"
+    if words.len()==3 && words[1]=="prefix" {
+        return Ok((n,Node::Prefix(Prefix {name: None, table: words[2].to_string()})))
+    }
+    if words.len()==5 && words[1]=="=" && words[3]=="prefix" {
+        return Ok((n,Node::Prefix(Prefix {name: Some(words[2].to_string()), table: words[4].to_string()})))
+    }
+
     // instr
     if words[1]=="=" {
-        match create_fmt(words,3,reverse) {
+        match create_fmt(words,3,reverse,wordbits) {
             Ok(fmt) => {return
                 Ok((n,Node::Instr((words[2].to_string(),fmt))))
             },
@@ -354,81 +704,231 @@ fn create_node(words: &Vec<&str>,mask: Bitmask,reverse: usize) -> Result<(Wordt,
         }
     }
     // map
-    return match gen_mask(words,1, reverse) {
+    match gen_mask(words,1, reverse) {
         Some( (m,_) ) => Ok((n,Node::Map(Maskmap{mask: m, map: HashMap::new()}))),
         None => Err(ErrType::NoMask(wordsvec_to_string(words)))
     }
 }
 
-pub fn parse_file(file: &File) -> Result<Instrset, (ErrType,u64)> {
-    // Curly {} braces represent nesting of Maskmaps. The Wordt is the index in the parent map
-    let mut braces: Vec<(Wordt, Maskmap)> = Vec::new();
-    // <reverse> should be set to 0 for no reversing, or wordsize to reverse all bitmasks by that
-    // many bits. Should be set when reading first line of file
+/*
+ * Bits needed to hold one word of a script, from its first line and
+ *  the lines declaring other modes, without parsing the rest.
+ * Used to pick the Word type before calling parse_file()
+ */
+pub fn script_wordbits<R: BufRead>(reader: R) -> Result<usize, (ErrType,u64)> {
+    let mut ln: u64=0;
+    let mut ret: usize=0;
+    for line in reader.lines() {
+        ln+=1;
+        match line {
+            Ok(l) => {
+                let words: Vec<&str> = l.split_whitespace().collect();
+                if words.is_empty() || words[0].starts_with('#') {continue;}
+                let decl=match ret {
+                    0 => &words[..],
+                    _ if words[0]=="mode" && words.len()>2 => &words[2..],
+                    _ => continue,
+                };
+                match parse_first_line(decl) {
+                    Ok((l,_)) => {ret=ret.max(l.storage_bits());},
+                    Err(why) => {return Err((why,ln))},
+                }
+            },
+            Err(why) => {return Err((ErrType::Internal(why),ln))},
+        }
+    }
+    match ret {
+        0 => Err((ErrType::Other,ln)),
+        n => Ok(n),
+    }
+}
+
+/*
+ * Parse a whole script. W must be wide enough for the script's word size
+ */
+pub fn parse_file<W: Word, R: BufRead>(reader: R) -> Result<Instrset<W>, (ErrType,u64)> {
+    // Curly {} braces represent nesting of Maskmaps. The W is the index in the parent map
+    let mut braces: Vec<(W, Maskmap<W>)> = Vec::new();
+    // <reverse> should be set to 0 for no reversing, or word size in bits to reverse all bitmasks
+    // by that many bits. Should be set when reading first line of file (or of a mode)
     let mut reverse: usize = 0;
 
     let mut d=Instrset {
-        endian_little: true,
-        wordsize: 0,
-        set: Maskmap {mask: 0, map: HashMap::new()},
+        modes: vec![Mode {
+            name: instrset::ROOT_TABLE.to_string(),
+            layout: Layout::bytes(),
+            set: Maskmap {mask: W::ZERO, map: HashMap::new()},
+        }],
+        tables: HashMap::new(),
+        data: Vec::new(),
+        aliases: Vec::new(),
+        warnings: Vec::new(),
+        strict: false,
     };
 
-    braces.push((0,d.set));
+    braces.push((W::ZERO,Maskmap {mask: W::ZERO, map: HashMap::new()}));
+    let mut mode: usize=0; // mode being read
+    let mut table: Option<String>=None; // table being read, None for the root set of <mode>
+    // tables named by prefixes and modes named by switches, checked at the end
+    let mut prefix_refs: Vec<(u64,String)>=Vec::new();
+    let mut switch_refs: Vec<(u64,String)>=Vec::new();
+    let mut alias_lines: Vec<u64>=Vec::new(); // line of each alias
+    let mut n_instrs: usize=0; // instructions read, to number them
 
     let mut ln: u64=0; // lines in file
     let mut lines_parsed=0; // non-comment/empty lines
+    let mut root_read=false; // first opcode mask of <mode> seen, so no more header lines
    
-    for line in io::BufReader::new(file).lines() {
+    for line in reader.lines() {
         ln+=1;
         match line {
             Ok(l) => {
                 let words: Vec<&str> = l.split_whitespace().collect();
 
                 // comments
-                if words.len()==0 || words[0].starts_with('#') {continue;}
+                if words.is_empty() || words[0].starts_with('#') {continue;}
+
+                // a description can end an instruction line
+                let (code,mut desc)=match split_description(&l) {
+                    Ok(split) => split,
+                    Err(why) => return Err((why,ln)),
+                };
+                let words: Vec<&str> = code.split_whitespace().collect();
+                if words.is_empty() {return Err((ErrType::BadDescription(l.trim().to_string()),ln))}
 
                 // First line (wordsize declaration)
                 if lines_parsed==0 { match parse_first_line(&words) {
-                    Ok((n,le,to_reverse)) => {
-                        d.wordsize=n;
-                        d.endian_little=le;
-                        if to_reverse {reverse=d.wordsize;}
+                    Ok((l,_)) if l.storage_bits()>W::BITS as usize => {
+                        return Err((ErrType::WordsizeTooLarge(l.storage_bits()),ln))
+                    },
+                    Ok((l,to_reverse)) => {
+                        if to_reverse {reverse=l.wordbits;}
+                        d.modes[0].layout=l;
                     },
                     Err(why) => { return Err((why,ln)) },
                 }}
 
-                // Second line (first opcode mask)
-                else if lines_parsed==1 { match parse_second_line(&words, &mut braces.last_mut().unwrap().1, reverse) {
+                // Aliases, anywhere after the first line
+                else if words[0]=="alias" { match parse_alias(&words) {
+                    Ok(a) => {
+                        d.aliases.push(a);
+                        alias_lines.push(ln);
+                    },
+                    Err(why) => return Err((why,ln)),
+                }}
+
+                // Header lines between the first line and the first opcode mask
+                else if !root_read && words[0]=="byte" { match parse_byte_order(&words, &mut d.modes[mode].layout) {
                     Ok(()) => (),
                     Err(why) => return Err((why,ln)),
                 }}
+                else if !root_read && mode==0 && words[0]=="mode" && words.len()==2 {
+                    d.modes[0].name=words[1].to_string();
+                }
+
+                // Data ranges, in the header or between sets
+                else if (!root_read || braces.is_empty()) && words[0]=="data" { match words[..] {
+                    [_,range,kind] => match parse_data_range(range,kind) {
+                        Ok(r) => {d.data.push(r);},
+                        Err(why) => return Err((why,ln)),
+                    },
+                    _ => return Err((ErrType::BadData(wordsvec_to_string(&words[1..])),ln)),
+                }}
+
+                // First opcode mask
+                else if !root_read { match parse_second_line(&words, &mut braces.last_mut().unwrap().1, reverse) {
+                    Ok(()) => {root_read=true;},
+                    Err(why) => return Err((why,ln)),
+                }}
 
                 // Closing braces
                 else if words[0]=="}" && words.len()==1 {
-                    if braces.len()==0 { return Err((ErrType::ExtraClosingBrace,ln)); }
+                    if braces.is_empty() { return Err((ErrType::ExtraClosingBrace,ln)); }
 
                     let tmp=braces.pop().unwrap();
-                    // final closing brace returns Instrset
-                    if braces.len()==0 {
-                        d.set=tmp.1;
-                        return Ok(d)
-                    }
+                    // final closing brace finishes the root set of a mode, or a table
+                    if braces.is_empty() { match table.take() {
+                        None => {d.modes[mode].set=tmp.1;},
+                        Some(name) => if d.tables.insert(name.clone(),tmp.1).is_some() {
+                            d.warnings.push((ln,format!("Table {} replaces an earlier table",name)));
+                        },
+                    }}
                     // otherwise move temp Maskmap off braces stack and into parent Maskmap
-                    else {braces.last_mut().unwrap().1.map.insert(tmp.0,Node::Map(tmp.1));}
+                    else if braces.last_mut().unwrap().1.map.insert(tmp.0,Node::Map(tmp.1)).is_some() {
+                        d.warnings.push((ln,format!("Map closed here replaces an earlier entry with opcode {:#x}",tmp.0)));
+                    }
+                }
+
+                // Other modes and tables, after the first root set
+                else if braces.is_empty() && words[0]=="mode" && words.len()>2 {
+                    if d.mode_index(words[1]).is_some() {
+                        return Err((ErrType::BadLayout(format!("Mode {} is declared twice",words[1])),ln))
+                    }
+                    match parse_first_line(&words[2..]) {
+                        Ok((l,_)) if l.storage_bits()>W::BITS as usize => {
+                            return Err((ErrType::WordsizeTooLarge(l.storage_bits()),ln))
+                        },
+                        Ok((l,to_reverse)) => {
+                            reverse=match to_reverse {true => l.wordbits, false => 0};
+                            d.modes.push(Mode {
+                                name: words[1].to_string(),
+                                layout: l,
+                                set: Maskmap {mask: W::ZERO, map: HashMap::new()},
+                            });
+                        },
+                        Err(why) => { return Err((why,ln)) },
+                    }
+                    mode=d.modes.len()-1;
+                    root_read=false;
+                    braces.push((W::ZERO,Maskmap {mask: W::ZERO, map: HashMap::new()}));
                 }
+                else if braces.is_empty() { match (words[0], words.get(1), gen_mask::<W>(&words,2,reverse)) {
+                    ("table", Some(name), Some((m,_))) if *name!=instrset::ROOT_TABLE => {
+                        table=Some(name.to_string());
+                        braces.push((W::ZERO,Maskmap {mask: m, map: HashMap::new()}));
+                    },
+                    _other => return Err((ErrType::ExpectedTable(wordsvec_to_string(&words)),ln)),
+                }}
 
                 // other lines
-                else {match create_node(&words,braces.last_mut().unwrap().1.mask,reverse) {
-                    Ok((i,n)) => match n {
-                        Node::Instr((ref _name,ref _fmt)) => {
-                            braces.last_mut().unwrap().1.map.insert(i,n);
+                else {
+                    let mask=braces.last().unwrap().1.mask;
+                    match create_node(&words,mask,reverse,d.modes[mode].layout.wordbits) {
+                    Ok((i,mut n)) => {
+                        if let Node::Instr((_,ifmt))=&mut n {
+                            ifmt.desc=desc.take();
+                            ifmt.id=n_instrs;
+                            n_instrs+=1;
+                        }
+                        // opcode bits which don't fit under the mask are dropped
+                        if let Ok(x)=parse_number::<W>(words[0]) {
+                            if x & !bits::low_bits::<W>(mask.count_ones() as usize) != W::ZERO {
+                                d.warnings.push((ln,format!("Opcode {} has more bits than its mask {:#x}",words[0],mask)));
+                            }
+                        }
+                        match &n {
+                            Node::Prefix(p) => {prefix_refs.push((ln,p.table.clone()));},
+                            Node::Instr((_,ifmt)) => if let Some(sw)=&ifmt.switch {
+                                switch_refs.push((ln,sw.mode.clone()));
+                            },
+                            Node::Map(_) => (),
+                        }
+                        match n {
+                        Node::Instr(_) | Node::Prefix(_) => {
+                            if braces.last_mut().unwrap().1.map.insert(i,n).is_some() {
+                                d.warnings.push((ln,format!("Opcode {} replaces an earlier entry",words[0])));
+                            }
                         },
                         Node::Map(map) => {braces.push((i,map));},
-                    },
+                    }},
                     Err(why) => {return Err((why,ln))}
                 }}
 
 
+                // only instructions have descriptions
+                if desc.is_some() {
+                    return Err((ErrType::BadDescription(l.trim().to_string()),ln))
+                }
                 lines_parsed+=1;
             },
             Err(why) => {
@@ -436,5 +936,29 @@ pub fn parse_file(file: &File) -> Result<Instrset, (ErrType,u64)> {
             }
         }
     }
-    Err((ErrType::Other,ln))
+
+    // every root set and table must be closed
+    if !root_read || !braces.is_empty() {return Err((ErrType::Other,ln))}
+    for (l,name) in prefix_refs {
+        if d.table(0,&name).is_none() {return Err((ErrType::UnknownTable(name),l))}
+    }
+    for (l,name) in switch_refs {
+        if d.mode_index(&name).is_none() {return Err((ErrType::UnknownMode(name),l))}
+    }
+    // every alias must be for some instruction, and fit all of them
+    let mut used=vec![false; d.aliases.len()];
+    for set in d.modes.iter_mut().map(|m| &mut m.set).chain(d.tables.values_mut()) {
+        if let Err(k)=attach_aliases(set,&d.aliases,&mut used) {
+            let (instr,a)=&d.aliases[k];
+            return Err((ErrType::BadAlias(format!("Alias {} names an operand instruction {} does not have",a.name,instr)),alias_lines[k]))
+        }
+    }
+    if let Some(k)=used.iter().position(|u| !u) {
+        return Err((ErrType::UnknownInstr(d.aliases[k].0.clone()),alias_lines[k]))
+    }
+    // addresses count bytes when there are several modes
+    if d.modes.len()>1 && d.modes.iter().any(|m| m.layout.container==0) {
+        return Err((ErrType::BadLayout("Scripts with several modes cannot have packed words".to_string()),ln))
+    }
+    Ok(d)
 }
@@ -0,0 +1,304 @@
+/*
+ * symbols.rs - names for addresses, from nm listings and linker maps
+ */
+use std::{
+    fs,
+    io,
+    fmt::Write,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use super::{
+    BranchTree, RefKind,
+    instrset::{
+        Word,
+        bits as bits,
+    },
+};
+
+pub struct Symbol {
+    pub name: String,
+    pub size: Option<u64>,
+    pub code: bool, // a function or other code, rather than data
+}
+
+/*
+ * Symbols by address. Addresses are in bytes, as symbol files give
+ *  them, and so are the values of address operands. Labels count
+ *  addresses of <unit> bytes each, so symbols not at the start of one
+ *  cannot name a label
+ */
+pub struct Symbols {
+    pub by_addr: BTreeMap<u64,Symbol>,
+    pub unit: u64,
+}
+
+/*
+ * Read an address as nm prints it (hex, without 0x) or as a map file
+ *  does (with 0x)
+ */
+fn parse_hex(text: &str) -> Option<u64> {
+    let digits=text.strip_prefix("0x").unwrap_or(text);
+    if digits.is_empty() {return None}
+    u64::from_str_radix(digits,16).ok()
+}
+
+/*
+ * Whether an nm symbol of type <t> is code: in the text section, or
+ *  weak and not an object
+ */
+fn is_code_type(t: &str) -> bool {
+    matches!(t,"T" | "t" | "W" | "w")
+}
+
+/*
+ * Whether a linker map section called <name> holds code
+ */
+fn is_code_section(name: &str) -> bool {
+    [".text",".init",".fini"].iter().any(|s| name==*s || name.starts_with(&format!("{}.",s)))
+}
+
+/*
+ * Whether <text> could be a symbol name
+ */
+fn is_name(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_alphabetic() || c=='_' || c=='.' || c=='$')
+}
+
+impl Symbols {
+    /*
+     * No symbols yet, for labels <unit> bytes apart (1 if words are not
+     *  stored in whole bytes)
+     */
+    pub fn new(unit: u64) -> Symbols {
+        Symbols {by_addr: BTreeMap::new(), unit: unit.max(1)}
+    }
+
+    /*
+     * Add the symbols in file <path>, returning how many were found.
+     * Understands lines from nm ("<addr> <type> <name>") and nm -S
+     *  ("<addr> <size> <type> <name>"), and symbol lines from linker maps
+     *  ("0x<addr> <name>"). Other lines, like undefined symbols or map
+     *  sections, are skipped, and so is anything after a #.
+     * Symbols are code if nm gives them a text or weak type, or if they
+     *  are in a .text, .init or .fini section of a map.
+     * The first name given for an address is kept.
+     */
+    pub fn read_file(&mut self, path: &str) -> Result<usize,String> {
+        let text=fs::read_to_string(path)
+            .map_err(|why| format!("Couldn't open symbol file {}: {}",path,why))?;
+
+        let mut n=0;
+        let mut in_code=false; // map section of the lines so far
+        for line in text.lines() {
+            let line=line.split_once('#').map_or(line,|(l,_)| l);
+            let words: Vec<&str>=line.split_whitespace().collect();
+            let (addr,size,name,code)=match words[..] {
+                [a,t,name] if t.len()==1 && is_name(name) => (parse_hex(a),None,name,is_code_type(t)),
+                [a,s,t,name] if t.len()==1 && is_name(name) => match parse_hex(s) {
+                    Some(s) => (parse_hex(a),Some(s),name,is_code_type(t)),
+                    None => continue,
+                },
+                [a,name] if a.starts_with("0x") && is_name(name) => (parse_hex(a),None,name,in_code),
+                // a map section, with its address and size if they fit
+                [sect,..] if sect.starts_with('.') && words.get(1).is_none_or(|a| a.starts_with("0x")) => {
+                    in_code=is_code_section(sect);
+                    continue
+                },
+                _ => continue,
+            };
+            if let Some(addr)=addr {
+                self.by_addr.entry(addr).or_insert(Symbol {name: name.to_string(), size, code});
+                n+=1;
+            }
+        }
+        Ok(n)
+    }
+
+    /*
+     * Name of the symbol at exactly label <label>
+     */
+    pub fn name(&self, label: u64) -> Option<&str> {
+        self.by_addr.get(&label.checked_mul(self.unit)?).map(|s| s.name.as_str())
+    }
+
+    /*
+     * Label at byte address <addr>, if it starts one
+     */
+    fn label(&self, addr: u64) -> Option<u64> {
+        addr.is_multiple_of(self.unit).then(|| addr/self.unit)
+    }
+
+    /*
+     * Labels of every symbol which starts one
+     */
+    pub fn labels(&self) -> impl Iterator<Item=u64> + '_ {
+        self.by_addr.keys().filter_map(|a| self.label(*a))
+    }
+
+    /*
+     * Labels of every code symbol which starts one, for entry points
+     */
+    pub fn code_labels(&self) -> impl Iterator<Item=u64> + '_ {
+        self.by_addr.iter()
+            .filter(|(_,s)| s.code)
+            .filter_map(|(a,_)| self.label(*a))
+    }
+
+    /*
+     * Label of the symbol called <name>
+     */
+    pub fn addr(&self, name: &str) -> Option<u64> {
+        self.by_addr.iter().find(|(_,s)| s.name==name)
+            .and_then(|(a,_)| self.label(*a))
+    }
+
+    /*
+     * Write every label in <tree> to <out> in a form read_file() reads
+     *  back (with byte addresses), with the kinds of reference to it
+     *  from <kinds> in a comment
+     */
+    pub fn export<O: io::Write>(&self, tree: &BranchTree, kinds: &BTreeMap<u64,BTreeSet<RefKind>>, out: &mut O)
+    -> io::Result<()> {
+        writeln!(out,"# <address> <type> <name> # <references>")?;
+        for addr in tree {
+            let mut name=String::new();
+            self.write_label(*addr,&mut name);
+            write!(out,"{:016x} t {}",addr*self.unit,name)?;
+            if let Some(k)=kinds.get(addr) {
+                let k: Vec<String>=k.iter().map(|k| k.to_string()).collect();
+                write!(out," # {}",k.join(", "))?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    /*
+     * Append the label for label address <addr> to <out>: its symbol, or
+     *  label_<addr>
+     */
+    pub fn write_label(&self, addr: u64, out: &mut String) {
+        // writing to a String cannot fail
+        let _=match self.name(addr) {
+            Some(name) => write!(out,"{}",name),
+            None => write!(out,"label_{:#x}",addr),
+        };
+    }
+
+    /*
+     * Append byte address <addr> to <out> as "symbol" or "symbol+offset"
+     *  if it is inside a symbol, or as a number otherwise.
+     * An address is inside the closest symbol below it: within its size
+     *  if known, or else before the next symbol
+     */
+    pub fn write_addr<W: Word>(&self, addr: W, out: &mut String) {
+        let a=addr.to_u64();
+        let inside=match addr<=bits::low_bits(64) {
+            true  => self.by_addr.range(..=a).next_back(),
+            false => None,
+        }.filter(|(start,sym)| match sym.size {
+            Some(size) => a-**start<size.max(1),
+            None => **start==a || self.by_addr.range(a..).next().is_some(),
+        });
+        let _=match inside {
+            Some((start,sym)) if *start==a => write!(out,"{}",sym.name),
+            Some((start,sym)) => write!(out,"{}+{:#x}",sym.name,a-start),
+            None => write!(out,"{:#x}",addr),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::instrset::Class;
+
+    /*
+     * Symbols read from <text>, written to a file called <name>
+     */
+    fn read(name: &str, text: &str, unit: u64) -> (Symbols,usize) {
+        let path=std::env::temp_dir().join(format!("asm-test-{}-{}",std::process::id(),name));
+        let path=path.to_string_lossy().to_string();
+        fs::write(&path,text).expect("Couldn't write symbol file");
+        let mut syms=Symbols::new(unit);
+        let n=syms.read_file(&path);
+        let _=fs::remove_file(&path);
+        match n {
+            Ok(n) => (syms,n),
+            Err(why) => panic!("{}",why),
+        }
+    }
+
+    fn addr(syms: &Symbols, a: u64) -> String {
+        let mut out=String::new();
+        syms.write_addr(a,&mut out);
+        out
+    }
+
+    #[test]
+    fn test_nm() {
+        let (syms,n)=read("nm","\
+            00000000 T main\n\
+            00000008 t func\n\
+            0000000c 00000004 D sized\n\
+            00000010 B buf\n\
+            00000012 b odd # not at a label\n\
+            \x20        U undefined\n",4);
+        assert!(n==5,"Actual: {}",n);
+        let code: Vec<u64>=syms.code_labels().collect();
+        assert!(code==[0,2],"Actual: {:?}",code);
+        let labels: Vec<u64>=syms.labels().collect();
+        assert!(labels==[0,2,3,4],"Actual: {:?}",labels);
+        assert!(syms.name(2)==Some("func") && syms.name(3)==Some("sized") && syms.name(1).is_none());
+        assert!(syms.addr("buf")==Some(4) && syms.addr("odd").is_none());
+    }
+
+    #[test]
+    fn test_nm_addr() {
+        let (syms,_)=read("nm_addr","00000010 B buf\n00000020 00000004 D sized\n",4);
+        assert!(addr(&syms,0x10)=="buf","Actual: {}",addr(&syms,0x10));
+        assert!(addr(&syms,0x12)=="buf+0x2","Actual: {}",addr(&syms,0x12));
+        assert!(addr(&syms,0x22)=="sized+0x2","Actual: {}",addr(&syms,0x22));
+        // past the end of the last symbol, and before the first
+        assert!(addr(&syms,0x24)=="0x24","Actual: {}",addr(&syms,0x24));
+        assert!(addr(&syms,0x4)=="0x4","Actual: {}",addr(&syms,0x4));
+    }
+
+    #[test]
+    fn test_map() {
+        let (syms,n)=read("map","\
+            .text           0x0000000000001000       0x20\n\
+            \x20.text          0x0000000000001000       0x20 main.o\n\
+            \x20               0x0000000000001000                main\n\
+            \x20               0x0000000000001010                helper\n\
+            .rodata.very_long_name\n\
+            \x20               0x0000000000001800       0x10 main.o\n\
+            \x20               0x0000000000001800                message\n\
+            .data           0x0000000000002000        0x8\n\
+            \x20               0x0000000000002000                table\n",1);
+        assert!(n==4,"Actual: {}",n);
+        let code: Vec<u64>=syms.code_labels().collect();
+        assert!(code==[0x1000,0x1010],"Actual: {:?}",code);
+        let labels: Vec<u64>=syms.labels().collect();
+        assert!(labels==[0x1000,0x1010,0x1800,0x2000],"Actual: {:?}",labels);
+        assert!(syms.name(0x1800)==Some("message"),"Actual: {:?}",syms.name(0x1800));
+    }
+
+    #[test]
+    fn test_export() {
+        let (syms,_)=read("export","00000008 t func\n",4);
+        let tree: BranchTree=[2,5].into_iter().collect();
+        let kinds=BTreeMap::from([(5,BTreeSet::from([RefKind {backward: false, relative: true, class: Some(Class::Call)}]))]);
+        let mut out=Vec::new();
+        syms.export(&tree,&kinds,&mut out).expect("Couldn't export");
+        let text=String::from_utf8(out).expect("Not UTF-8");
+        assert!(text.contains("0000000000000014 t label_0x5 # forward relative call"),"Actual: {}",text);
+
+        let (back,n)=read("export_back",&text,4);
+        assert!(n==2,"Actual: {}",n);
+        let labels: Vec<u64>=back.labels().collect();
+        assert!(labels==[2,5],"Actual: {:?}",labels);
+        assert!(back.name(2)==Some("func") && back.name(5)==Some("label_0x5"),"Actual: {}",text);
+    }
+}
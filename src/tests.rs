@@ -1,6 +1,5 @@
 /*
- * NOTE: This file currently does not get loaded anywhere
- * It's just to "keep" testcases around that have already passed
+ * tests.rs - tests of the script parser and bit helpers
  */
 
 #[cfg(test)]
@@ -9,32 +8,39 @@ mod bits_tests {
 
     #[test]
     fn test_reverse() {
-        let a: bits::Wordt=0b01000000000000000000000000000001;
-        let result=bits::reverse(&a,4);
+        let a: u64=0b01000000000000000000000000000001;
+        let result=bits::reverse(&a,32);
         assert!(result==0b10000000000000000000000000000010,
         "actual: {:#b}",result);
     }
     #[test]
     fn test_minimize() {
-        let result=bits::minimize(0b10000011,0b11000011);
-        let maxbit: bits::Wordt = 1 << (bits::Wordt::BITS-1);
+        let result=bits::minimize::<u64>(0b10000011,0b11000011);
+        let maxbit: u64 = 1 << (u64::BITS-1);
         assert!(result.0==0b1011 && result.1==4,
             "Actual: ({:#b},{}); MAXBIT={:#b}",result.0,result.1,maxbit);
     }
 
+    #[test]
+    fn test_minimize_wide() {
+        let result=bits::minimize::<u128>(1<<100|1,1<<100|1<<99|1);
+        assert!(result.0==0b101 && result.1==3,
+            "Actual: ({:#b},{})",result.0,result.1);
+    }
+
     #[test]
     fn test_align() {
-        let result=bits::align(0b1011,0b11000011);
+        let result=bits::align::<u64>(0b1011,0b11000011);
         assert!(result==0b10000011,
             "Actual: {:#b}",result);
     }
 
     #[test]
     fn test_twoscomp() {
-        let result=bits::twoscomp((0b1011,4));
+        let result=bits::twoscomp::<u64>((0b1011,4));
         assert!(result== -5,
                 "Actual: {:#b}={}",result,result);
-        assert!(bits::twoscomp((0b011,3))==3);
+        assert!(bits::twoscomp::<u64>((0b011,3))==3);
     }
 }
 
@@ -43,13 +49,13 @@ mod genmask_tests {
     use crate::parse;
     #[test]
     fn test_genmask() {
-        let result: (parse::Bitmask, usize);
+        let result: (u64, usize);
         if let Some(m)=parse::gen_mask(
-                &vec!["mask","0b1101"],
+                &["mask","0b1101"],
                 0, 0) {
             result=m;
         }
-        else {assert!(0==1); return}
+        else {panic!("no mask")}
         assert!(
             result == (0b1101,2),
             "Actual: ({:#b},{})",result.0,result.1
@@ -58,13 +64,13 @@ mod genmask_tests {
 
     #[test]
     fn test_genmask_bits() {
-        let result: (parse::Bitmask, usize);
+        let result: (u64, usize);
         if let Some(m)=parse::gen_mask(
-                &vec!["0+1:2+15"],
-                0, 2) {
+                &["0+1:2+15"],
+                0, 16) {
             result=m;
         }
-        else {assert!(0==1); return}
+        else {panic!("no mask")}
         assert!(
             result == (0b1110000000000001,1),
             "Actual: ({:#b},{})",result.0,result.1
@@ -72,3 +78,157 @@ mod genmask_tests {
     }
 }
 
+
+#[cfg(test)]
+mod layout_tests {
+    use crate::parse::{self, ErrType};
+
+    fn layout(line: &str) -> Result<(usize,usize,bool),String> {
+        let words: Vec<&str>=line.split_whitespace().collect();
+        match parse::parse_first_line(&words) {
+            Ok((l,_)) => Ok((l.wordbits,l.container,l.check_padding)),
+            Err(ErrType::BadLayout(why)) => Err(why),
+            Err(why) => panic!("{}: {}",line,why),
+        }
+    }
+
+    #[test]
+    fn test_layouts() {
+        for (line,want) in [
+            ("2 byte words", (16,2,false)),
+            ("12 bit packed words", (12,0,false)),
+            ("14 bit checked words in 2 byte containers", (14,2,true)),
+        ] {
+            let result=layout(line);
+            assert!(result==Ok(want),"{}: {:?}",line,result);
+        }
+    }
+
+    #[test]
+    fn test_bad_layouts() {
+        for line in [
+            "12 bit words",
+            "12 bit packed words in 2 byte containers",
+            "17 bit words in 2 byte containers",
+            "16 bit checked words in 2 byte containers",
+            // sizes whose bits overflow
+            "2305843009213693952 byte words",
+            "4 bit words in 18446744073709551615 byte containers",
+        ] {
+            let result=layout(line);
+            assert!(result.is_err(),"{}: {:?}",line,result);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod trailing_tests {
+    use crate::parse::{self, ErrType, MAX_TRAILING_WORDS};
+
+    fn fmt(instr: &str) -> Result<(),ErrType> {
+        let text=format!("1 byte words\nmask 0xff {{\n0x0 = {}\n}}\n",instr);
+        match parse::parse_file::<u64,_>(text.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err((why,_)) => Err(why),
+        }
+    }
+
+    #[test]
+    fn test_trailing_words() {
+        for instr in [
+            "ld +1 word uint word 1 0:7",
+            "ld +2 words uint words 1:2 0:15 int word 0 0:3",
+            &format!("ld +{} words uint word 1 0:7",MAX_TRAILING_WORDS),
+        ] {
+            let result=fmt(instr);
+            assert!(result.is_ok(),"{}: {}",instr,result.err().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_bad_trailing_words() {
+        for instr in [
+            "ld +1 uint word 1 0:7",
+            "ld +1 word uint word 2 0:7",
+            "ld +1 word uint words 2:1 0:7",
+            "ld +9 words uint words 0:8 0:7",
+            &format!("ld +{} words uint word 1 0:7",MAX_TRAILING_WORDS+1),
+            "ld +18446744073709551615 words uint word 1 0:7",
+        ] {
+            let result=fmt(instr);
+            assert!(matches!(result,Err(ErrType::BadOperandWords(_))),"{}: {:?}",instr,result.is_ok());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod description_tests {
+    use crate::parse::{self, ErrType};
+
+    #[test]
+    fn test_split_description() {
+        let Ok((code,desc))=parse::split_description(r#"0x0 = ld "Load \"x\" from \\ here""#) else {panic!("rejected")};
+        assert!(code=="0x0 = ld " && desc.as_deref()==Some(r#"Load "x" from \ here"#),"Actual: {} {:?}",code,desc);
+        let Ok((code,desc))=parse::split_description("0x0 = nop") else {panic!("rejected")};
+        assert!(code=="0x0 = nop" && desc.is_none(),"Actual: {} {:?}",code,desc);
+    }
+
+    #[test]
+    fn test_bad_descriptions() {
+        // anything after the description, or no closing quote
+        for line in [r#"0x0 = ld "Load" uint 0:3"#, r#"0x0 = ld "Load \" here"#] {
+            let result=parse::split_description(line);
+            assert!(matches!(result,Err(ErrType::BadDescription(_))),"{}: {:?}",line,result.is_ok());
+        }
+
+        // only instructions have descriptions
+        let text="1 byte words\nmask 0xff { \"Opcodes\"\n0x0 = nop\n}\n";
+        let result=parse::parse_file::<u64,_>(text.as_bytes());
+        assert!(matches!(result,Err((ErrType::BadDescription(_),2))),"Accepted: {:?}",result.is_ok());
+    }
+}
+
+
+#[cfg(test)]
+mod reserved_tests {
+    use crate::parse::{self, ErrType};
+    use crate::parse::deassemble::branch::instrset::{self, Node};
+
+    fn fmt(instr: &str) -> Result<(u64,u64),ErrType> {
+        let text=format!("2 byte words\nmask 0xff00 {{\n0x1 = {}\n}}\n",instr);
+        match parse::parse_file::<u64,_>(text.as_bytes()) {
+            Ok(is) => match instrset::get_node(0x100,&is.modes[0].set,&mut 0) {
+                Some(Node::Instr(entry)) => Ok(entry.1.reserved),
+                _other => panic!("no instruction"),
+            },
+            Err((why,_)) => Err(why),
+        }
+    }
+
+    #[test]
+    fn test_reserved() {
+        for (instr,want) in [
+            ("ld uint mask 0xf reserved bits 4:7 = 0",(0xf0,0)),
+            ("ld uint mask 0xf reserved mask 0xf0 = 0x5",(0xf0,0x50)),
+            ("ld reserved bits 4:5 = 0x1 reserved bits 7 = 1",(0xb0,0x90)),
+        ] {
+            let result=fmt(instr);
+            assert!(matches!(result,Ok(r) if r==want),"{}: {:?}",instr,result.ok());
+        }
+    }
+
+    #[test]
+    fn test_bad_reserved() {
+        for instr in [
+            "ld reserved bits 4:7",
+            "ld reserved bits 4:7 = ",
+            "ld reserved bits 4:5 = 0x4",
+            "ld reserved = 0",
+        ] {
+            let result=fmt(instr);
+            assert!(matches!(result,Err(ErrType::BadReserved(_))),"{}: {:?}",instr,result.is_ok());
+        }
+    }
+}